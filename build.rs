@@ -0,0 +1,25 @@
+use std::process::Command;
+
+fn main() {
+    // Fall back to "unknown" when building outside a git checkout
+    let git_sha = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8(out.stdout).ok())
+        .map(|sha| sha.trim().to_string())
+        .filter(|sha| !sha.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    let build_time = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+
+    println!("cargo:rustc-env=NONAP_GIT_SHA={}", git_sha);
+    println!("cargo:rustc-env=NONAP_BUILD_TIME={}", build_time);
+    // Rebuild when HEAD moves, whether by checkout or by a new commit on the branch
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    if let Ok(head) = std::fs::read_to_string(".git/HEAD") {
+        if let Some(branch_ref) = head.trim().strip_prefix("ref: ") {
+            println!("cargo:rerun-if-changed=.git/{}", branch_ref);
+        }
+    }
+}
@@ -1,327 +1,4992 @@
+use base64::Engine;
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use futures_util::{future::join_all, stream, SinkExt, Stream, StreamExt};
+use hmac::{Hmac, Mac};
 use parking_lot::Mutex;
+use percent_encoding::percent_decode_str;
 use rand::Rng;
-use reqwest::Client;
+use regex::Regex;
+use reqwest::{
+    cookie::Jar,
+    header::{
+        HeaderMap, HeaderName, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+        RETRY_AFTER,
+    },
+    redirect, Client, ClientBuilder, Method, NoProxy, Proxy,
+};
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use std::{
-    fs::{self, OpenOptions},
-    io::Write,
-    sync::Arc,
-    time::Duration,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    convert::Infallible,
+    fs::{self, File, OpenOptions},
+    io::{IsTerminal, Write},
+    net::{IpAddr, SocketAddr},
+    num::NonZeroUsize,
+    path::Path,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, oneshot, OwnedSemaphorePermit, Semaphore,
+    },
+    task::JoinHandle,
+    time::{sleep, sleep_until},
+};
+use tokio_util::sync::CancellationToken;
+use warp::{
+    filters::BoxedFilter,
+    http::StatusCode,
+    sse,
+    ws::{Message, WebSocket},
+    Filter,
 };
-use tokio::{task::JoinHandle, time::sleep};
-use warp::{http::StatusCode, Filter};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 struct PingTarget {
     url: String,
-    min_delay: u64,
-    max_delay: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_delay: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_delay: Option<u64>,
+    /// Fixed cadence that replaces the random min/max range
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    interval: Option<u64>,
+    /// Midpoint of a jittered delay; replaces interval and the min/max range
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    base_delay: Option<u64>,
+    /// How far, in percent of `base_delay`, each delay may stray either way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    jitter_pct: Option<u8>,
+    /// Unit for the delay fields: "seconds", "minutes" (default) or "hours"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timeout_secs: Option<u64>,
+    /// Enables backoff after repeated failures, capping the delay multiplier
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_backoff_mult: Option<u64>,
+    /// Consecutive failures before backoff engages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    backoff_after: Option<u64>,
+    /// Extra attempts after a failed request before it counts as a failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retries: Option<u32>,
+    /// Extra request headers, e.g. an API key or User-Agent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    headers: Option<HashMap<String, String>>,
+    /// Exact status that counts as healthy; defaults to any 2xx/3xx
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expected_status: Option<u16>,
+    /// Status class that counts as healthy, e.g. "2xx"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expected_range: Option<String>,
+    /// Follow 3xx redirects (the default); when false the raw redirect is checked
+    /// against `expected_status`/`expected_range`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    follow_redirects: Option<bool>,
+    /// Substring the body must contain for the ping to count as healthy. Only checked
+    /// once the status passes `expected_status`/`expected_range`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body_contains: Option<String>,
+    /// Regex the body must match; mutually exclusive with `body_contains`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body_regex: Option<String>,
+    /// Number of parallel loops pinging this URL (default 1, at most
+    /// `MAX_TARGET_CONCURRENCY`); they share its stats. Ignored for grouped targets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    concurrency: Option<usize>,
+    /// Targets sharing a group are pinged together by one loop, on the schedule
+    /// of the group's first enabled member
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    /// Total pings ever sent (across restarts) before the loop stops for good
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_pings: Option<u64>,
+    /// Include the start of the response body in the log when the status is unexpected
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    capture_body_on_failure: bool,
+    /// Daily ("HH:MM", "HH:MM") window where pings are skipped; may cross midnight
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quiet_hours: Option<(String, String)>,
+    /// IANA timezone for `quiet_hours`, e.g. "Europe/Berlin"; defaults to UTC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timezone: Option<String>,
+    /// Free-form labels (environment, team, ...) for filtering; they don't affect pinging
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tags: Option<Vec<String>>,
+    /// "http" (default), "tcp", which only checks that `host:port` from the URL
+    /// accepts a connection, or "dns", which only resolves the URL's host.
+    /// HTTP-specific settings are ignored unless it's "http"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    check_type: Option<String>,
+    /// "v4" or "v6" to resolve and connect over that address family only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ip_family: Option<String>,
+    /// Request payload, sent only with POST, PUT and PATCH
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+    /// `Content-Type` sent along with `body`, e.g. "application/json"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    /// HTTP Basic credentials as (user, password); both may use `${VAR}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    basic_auth: Option<(String, String)>,
+    /// Consecutive failures before a degraded target counts as down (default 3)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    down_after: Option<u64>,
+    /// Send `If-None-Match`/`If-Modified-Since` from the last response; a 304 counts as healthy
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    conditional: bool,
+    /// Bodies are never read past this; a larger response logs a warning
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_response_bytes: Option<usize>,
+    /// Count a response over `max_response_bytes` as a failure instead of a warning
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    fail_on_oversize: bool,
+    /// Rapid pings fired when the loop starts, before the normal schedule, to
+    /// wake a cold target. Ignored for grouped targets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    warmup_pings: Option<u32>,
+    /// Keep cookies set by responses and send them on later pings, in a jar of
+    /// the target's own
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    persist_cookies: bool,
+    /// Sign every request with an HMAC-SHA256 over the current timestamp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hmac: Option<HmacConfig>,
+    /// Stop the normal schedule for a hopeless target and only probe it now
+    /// and then until it recovers. Ignored for grouped targets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    circuit_breaker: Option<CircuitBreaker>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+struct CircuitBreaker {
+    /// Consecutive failures that open the circuit
+    failures: u64,
+    /// How long the failure streak must also have lasted (default 0)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    window_secs: Option<u64>,
+    /// Seconds between probes while the circuit is open (default 300)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reset_timeout_secs: Option<u64>,
+}
+
+impl CircuitBreaker {
+    fn reset_timeout_secs(&self) -> u64 {
+        self.reset_timeout_secs
+            .unwrap_or(DEFAULT_CIRCUIT_RESET_SECS)
+    }
+
+    /// Whether a failure streak is long enough, in count and time, to open the circuit.
+    fn trips(&self, stats: &TargetStats) -> bool {
+        let window = self.window_secs.unwrap_or(0);
+        stats.consecutive_failures >= self.failures
+            && secs_since(stats.failing_since.as_deref()).unwrap_or(0) >= window as i64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+struct HmacConfig {
+    /// Environment variable holding the signing secret
+    secret_env: String,
+    /// Header the hex-encoded signature is sent in
+    header_name: String,
+    /// Header the signed Unix timestamp is sent in (default "X-Timestamp")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp_header: Option<String>,
+}
+
+impl PingTarget {
+    fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().flatten().any(|t| t == tag)
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+struct TargetStats {
+    success_count: u64,
+    failure_count: u64,
+    consecutive_failures: u64,
+    last_status: Option<u16>,
+    /// Latency of the last response, if one arrived
+    last_latency_ms: Option<u64>,
+    /// RFC3339 timestamp of the last completed attempt
+    last_checked: Option<String>,
+    /// Set by a failure and cleared by the next success
+    was_failing: bool,
+    /// RFC3339 timestamp of the first failure in the current streak
+    failing_since: Option<String>,
+    /// Failed checks by what went wrong, over the target's lifetime
+    failures_by_category: BTreeMap<ErrorCategory, u64>,
+}
+
+impl TargetStats {
+    /// Forgets the current failure streak, returning how long it was.
+    fn clear_failure_streak(&mut self) -> u64 {
+        let failures = self.consecutive_failures;
+        self.consecutive_failures = 0;
+        self.was_failing = false;
+        self.failing_since = None;
+        failures
+    }
 }
 
+/// A running ping loop and the token that stops it.
+type Worker = (JoinHandle<()>, CancellationToken);
+
 #[derive(Debug)]
 struct AppState {
     targets: Vec<PingTarget>,
     running: bool,
-    handles: Vec<JoinHandle<()>>,
-    logs: Vec<String>,
+    /// Loops keep their schedule but skip sending requests while set
+    paused: bool,
+    /// Running ping loops keyed by target URL
+    handles: HashMap<String, Worker>,
+    /// Parent of every worker's token; cancelled (and replaced) on stop
+    cancel: CancellationToken,
+    /// Cancelled once when the process is shutting down
+    shutdown: CancellationToken,
+    logs: Vec<LogEntry>,
+    /// Most entries kept in `logs` (`NONAP_LOG_BUFFER`)
+    log_buffer: usize,
+    /// Entries below this level are dropped (`NONAP_LOG_LEVEL`)
+    log_level: LogLevel,
+    /// Feeds the background writer that owns nonap.log
+    log_writer: mpsc::UnboundedSender<LogCommand>,
+    /// Last `recent_window` checks per target URL
+    recent: HashMap<String, VecDeque<RecentResult>>,
+    recent_window: usize,
+    /// Last `ETag`/`Last-Modified` per target URL, for `conditional` targets
+    validators: HashMap<String, CacheValidators>,
+    /// When each target's loop is next due to ping it, set as the loop starts sleeping
+    next_ping: HashMap<String, DateTime<Utc>>,
+    /// Cookie jar per `persist_cookies` target URL; outlives its loops so a
+    /// restart keeps the session
+    cookie_jars: HashMap<String, Arc<Jar>>,
+    /// When each target's circuit breaker opened; closed circuits have no entry
+    circuits: HashMap<String, DateTime<Utc>>,
+    /// SQLite history of pings and logs, when `NONAP_DB_PATH` is set
+    history: Option<mpsc::UnboundedSender<HistoryCommand>>,
+    /// Fan-out of new log lines to connected WebSocket clients
+    log_tx: broadcast::Sender<String>,
+    stats: HashMap<String, TargetStats>,
+    /// Receives failure notifications when set
+    webhook_url: Option<String>,
+    alert_format: AlertFormat,
+    started_at: Instant,
+    /// Shared HTTP client for every ping and webhook, proxy included
+    client: Client,
+    /// Floor applied to every computed delay
+    min_interval_secs: u64,
+    /// `NONAP_DEFAULT_MIN`/`NONAP_DEFAULT_MAX`, for `/add-target` bodies without a delay
+    default_delay: (Option<u64>, Option<u64>),
+    /// First pings on start/reload are spread over this many seconds
+    stagger_secs: u64,
+    /// Cap on the response body kept for targets with `capture_body_on_failure`
+    failure_body_bytes: usize,
+    /// Bounds checks in flight across all targets (`NONAP_MAX_CONCURRENCY`)
+    request_slots: Option<Arc<Semaphore>>,
+    /// Targets file used at startup, by /reload and when persisting changes
+    config_path: String,
+    /// Set while the last load of the config failed or skipped entries, so
+    /// persisting would clobber them; cleared by a clean reload
+    persist_blocked: Option<String>,
 }
 
 type SharedState = Arc<Mutex<AppState>>;
 
 const LOG_FILE_PATH: &str = "nonap.log";
+const DEFAULT_CONFIG_PATH: &str = "targets.json";
+const STATS_FILE_PATH: &str = "stats.json";
+const STATS_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_BIND: &str = "0.0.0.0:3030";
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_MIN_INTERVAL_SECS: u64 = 5;
+const LOG_BROADCAST_CAPACITY: usize = 100;
+const WORKER_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_BACKOFF_AFTER: u64 = 3;
+const MAX_TARGET_CONCURRENCY: usize = 16;
+const MAX_SLEEP: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+const DEFAULT_DOWN_AFTER: u64 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+const WARMUP_INTERVAL: Duration = Duration::from_secs(3);
+const DEFAULT_CIRCUIT_RESET_SECS: u64 = 300;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_LOG_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_LOG_KEEP: usize = 5;
+const LOG_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+const CONFIG_WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+const DEFAULT_FAILURE_BODY_BYTES: usize = 2048;
+const DEFAULT_LOG_BUFFER: NonZeroUsize = NonZeroUsize::new(100).unwrap();
+const DEFAULT_LATENCY_WINDOW: usize = 500;
+const DEFAULT_HISTORY_LIMIT: usize = 500;
+const MAX_HISTORY_LIMIT: usize = 5000;
+const BODY_MATCH_MAX_BYTES: usize = 1024 * 1024;
+const RATE_LIMIT_MAX_CLIENTS: usize = 10_000;
 
 #[tokio::main]
 async fn main() {
+    // Validate the config and exit without binding or spawning anything
+    if std::env::args().any(|arg| arg == "--check") {
+        let valid = check_config(&config_path());
+        std::process::exit(if valid { 0 } else { 1 });
+    }
+
     println!("🚀 NoNap microservice with control API started!");
 
-    let initial_targets = load_targets_from_file("targets.json").unwrap_or_default();
+    // Misconfigured TLS should stop us before any worker starts
+    let tls = match tls_paths() {
+        Ok(tls) => tls,
+        Err(e) => {
+            eprintln!("❌ [NoNap] {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let client = match build_client() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("❌ [NoNap] {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // NonZeroUsize rejects 0 with a warning, like any other unparsable value
+    let log_buffer = env_parse("NONAP_LOG_BUFFER", DEFAULT_LOG_BUFFER).get();
+    let (history, initial_logs) = match std::env::var("NONAP_DB_PATH")
+        .ok()
+        .filter(|p| !p.is_empty())
+    {
+        Some(path) => match open_history(&path, log_buffer) {
+            Ok((conn, recent)) => {
+                println!("🗄️ Recording history in {}", path);
+                (Some(spawn_history_writer(conn)), recent)
+            }
+            Err(e) => {
+                eprintln!("❌ [NoNap] {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => (None, Vec::new()),
+    };
+
+    let config_path = config_path();
+    println!("📄 Using config {}", config_path);
+    // The log sinks only exist once AppState is built, so the load outcome is
+    // held here and appended right after
+    let mut startup_log: Option<(LogLevel, String)> = None;
+    let (initial_targets, persist_blocked) = match load_targets_from_file(&config_path) {
+        Ok((targets, skipped)) => (targets, lossy_load_reason(skipped)),
+        Err(ConfigError::Missing(_)) => {
+            let msg = format!(
+                "ℹ️ [NoNap] No config found at {}, starting empty",
+                config_path
+            );
+            println!("{}", msg);
+            startup_log = Some((LogLevel::Info, msg));
+            (Vec::new(), None)
+        }
+        Err(e) => {
+            let msg = format!("❌ [NoNap] {}", e);
+            eprintln!("{}", msg);
+            startup_log = Some((LogLevel::Error, msg));
+            if env_flag("NONAP_STRICT_CONFIG") {
+                eprintln!(
+                    "❌ [NoNap] Refusing to start with a broken config (NONAP_STRICT_CONFIG)"
+                );
+                std::process::exit(1);
+            }
+            // Saving is held off so API changes can't overwrite the broken file
+            eprintln!(
+                "⚠️ [NoNap] Starting with no targets; fix {} and reload, or set NONAP_STRICT_CONFIG to refuse to start",
+                config_path
+            );
+            (Vec::new(), Some("failed to load".to_string()))
+        }
+    };
+    let initial_stats = load_stats_from_file(STATS_FILE_PATH, &initial_targets);
+
+    let (log_tx, _) = broadcast::channel(LOG_BROADCAST_CAPACITY);
 
     let state = Arc::new(Mutex::new(AppState {
         targets: initial_targets,
         running: false,
-        handles: vec![],
-        logs: vec![],
+        paused: false,
+        handles: HashMap::new(),
+        cancel: CancellationToken::new(),
+        shutdown: CancellationToken::new(),
+        logs: initial_logs,
+        log_buffer,
+        log_level: env_parse("NONAP_LOG_LEVEL", LogLevel::Info),
+        log_writer: spawn_log_writer(LogWriterConfig::from_env()),
+        history,
+        recent: HashMap::new(),
+        validators: HashMap::new(),
+        next_ping: HashMap::new(),
+        cookie_jars: HashMap::new(),
+        circuits: HashMap::new(),
+        recent_window: env_parse("NONAP_LATENCY_WINDOW", DEFAULT_LATENCY_WINDOW).max(1),
+        log_tx,
+        stats: initial_stats,
+        webhook_url: std::env::var("NONAP_WEBHOOK_URL")
+            .ok()
+            .filter(|u| !u.is_empty()),
+        alert_format: AlertFormat::from_env(),
+        started_at: Instant::now(),
+        client,
+        min_interval_secs: env_parse("NONAP_MIN_INTERVAL_SECS", DEFAULT_MIN_INTERVAL_SECS),
+        failure_body_bytes: env_parse("NONAP_FAILURE_BODY_BYTES", DEFAULT_FAILURE_BODY_BYTES),
+        request_slots: env_parse_opt::<NonZeroUsize>("NONAP_MAX_CONCURRENCY")
+            .map(|limit| Arc::new(Semaphore::new(limit.get()))),
+        stagger_secs: env_parse("NONAP_STAGGER_SECS", 0),
+        default_delay: (
+            env_parse_opt("NONAP_DEFAULT_MIN"),
+            env_parse_opt("NONAP_DEFAULT_MAX"),
+        ),
+        config_path,
+        persist_blocked,
     }));
 
-    // Start pinging immediately on launch
-    {
-        let mut locked = state.lock();
-        locked.running = true;
+    if let Some((level, msg)) = startup_log {
+        append_log(state.clone(), level, msg);
+    }
 
-        let client = Client::new();
-        locked.handles = vec![];
+    tokio::spawn(stats_saver(state.clone()));
 
-        for target in locked.targets.clone() {
-            let c = client.clone();
-            let s = state.clone();
-            let handle = tokio::spawn(async move { ping_loop(target, c, s).await });
-            locked.handles.push(handle);
+    if env_flag("NONAP_WATCH_CONFIG") {
+        match spawn_config_watcher(state.clone()) {
+            Ok(()) => println!("👀 Watching {} for changes", state.lock().config_path),
+            Err(e) => eprintln!("⚠️ [NoNap] {}", e),
         }
     }
 
+    // Start pinging immediately on launch, unless told to wait for /start
+    if env_flag_or("NONAP_AUTOSTART", true) {
+        let mut locked = state.lock();
+        locked.running = true;
+
+        spawn_all_workers(&state, &mut locked);
+    } else {
+        println!("💤 NONAP_AUTOSTART is off, waiting for POST /start");
+    }
+
     // Clone state for warp filters
     let with_state = warp::any().map({
         let state = state.clone();
         move || state.clone()
     });
 
+    // Bearer-token auth: mutating routes always require it once NONAP_TOKEN is set,
+    // read-only routes only when NONAP_TOKEN_READS is enabled as well
+    let token = std::env::var("NONAP_TOKEN").ok().filter(|t| !t.is_empty());
+    let protect_reads = token.is_some() && env_flag("NONAP_TOKEN_READS");
+    // Basic auth lets browsers prompt for the dashboard; it guards read-only routes,
+    // and mutating ones too with NONAP_BASIC_ALL. Either credential is accepted
+    // where both apply
+    let basic = basic_credentials();
+    let basic_all = basic.is_some() && env_flag("NONAP_BASIC_ALL");
+    // Off by default: a stray request should never be able to stop the process
+    let allow_remote_shutdown = env_flag("NONAP_ALLOW_REMOTE_SHUTDOWN");
+    if allow_remote_shutdown && token.is_none() && !basic_all {
+        eprintln!(
+            "⚠️ [NoNap] NONAP_ALLOW_REMOTE_SHUTDOWN is set without NONAP_TOKEN; anyone who can reach the API can stop it"
+        );
+    }
+    // Reads get a more generous budget than mutating routes by default
+    let write_limit = env_parse("NONAP_RATE_LIMIT", 0u32);
+    let read_limit = env_parse("NONAP_RATE_LIMIT_READS", write_limit.saturating_mul(4));
+    let auth = rate_limit(RateLimiter::new(write_limit)).and(require_auth(AuthConfig {
+        token: token.clone(),
+        basic: basic.clone().filter(|_| basic_all),
+    }));
+    let read_auth = rate_limit(RateLimiter::new(read_limit)).and(require_auth(AuthConfig {
+        token: token.filter(|_| protect_reads),
+        basic,
+    }));
+
     // Routes
     let status_route = warp::path!("status")
         .and(warp::get())
+        .and(read_auth.clone())
+        .and(warp::query::<StatusQuery>())
         .and(with_state.clone())
         .and_then(handle_status);
 
+    let status_history_route = warp::path!("status" / "history")
+        .and(warp::get())
+        .and(read_auth.clone())
+        .and(warp::query::<StatusHistoryQuery>())
+        .and(with_state.clone())
+        .and_then(handle_status_history);
+
     let start_route = warp::path!("start")
         .and(warp::post())
+        .and(auth.clone())
         .and(with_state.clone())
         .and_then(handle_start);
 
     let stop_route = warp::path!("stop")
         .and(warp::post())
+        .and(auth.clone())
         .and(with_state.clone())
         .and_then(handle_stop);
 
+    let restart_route = warp::path!("restart")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(with_state.clone())
+        .and_then(handle_restart);
+
+    let pause_route = warp::path!("pause")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(with_state.clone())
+        .and_then(handle_pause);
+
+    let resume_route = warp::path!("resume")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(with_state.clone())
+        .and_then(handle_resume);
+
     let get_targets_route = warp::path!("targets")
         .and(warp::get())
+        .and(read_auth.clone())
         .and(with_state.clone())
         .and_then(handle_get_targets);
 
+    // Behind the token even without NONAP_TOKEN_READS, as it spells out every target's setup
+    let config_route = warp::path!("config")
+        .and(warp::get())
+        .and(auth.clone())
+        .and(with_state.clone())
+        .and_then(handle_config);
+
     let add_target_route = warp::path!("add-target")
         .and(warp::post())
+        .and(auth.clone())
         .and(warp::body::json())
         .and(with_state.clone())
         .and_then(handle_add_target);
 
+    let add_targets_route = warp::path!("add-targets")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::body::json())
+        .and(with_state.clone())
+        .and_then(handle_add_targets);
+
     let remove_target_route = warp::path!("remove-target")
         .and(warp::post())
+        .and(auth.clone())
         .and(warp::body::json())
         .and(with_state.clone())
         .and_then(handle_remove_target);
 
+    let delete_target_route = warp::path!("targets" / String)
+        .and(warp::delete())
+        .and(auth.clone())
+        .and(with_state.clone())
+        .and_then(handle_delete_target);
+
+    let target_stats_route = warp::path!("targets" / String / "stats")
+        .and(warp::get())
+        .and(read_auth.clone())
+        .and(with_state.clone())
+        .and_then(handle_target_stats);
+
+    let reset_backoff_route = warp::path!("targets" / String / "reset-backoff")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(with_state.clone())
+        .and_then(handle_reset_backoff);
+
+    let reset_circuit_route = warp::path!("targets" / String / "reset-circuit")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(with_state.clone())
+        .and_then(handle_reset_circuit);
+
+    let toggle_target_route = warp::path!("toggle-target")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::body::json())
+        .and(with_state.clone())
+        .and_then(handle_toggle_target);
+
+    let update_target_route = warp::path!("update-target")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::body::json())
+        .and(with_state.clone())
+        .and_then(handle_update_target);
+
+    let ping_now_route = warp::path!("ping-now")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::body::json())
+        .and(with_state.clone())
+        .and_then(handle_ping_now);
+
+    // Liveness/readiness probe; deliberately never behind auth
+    let health_route = warp::path!("health")
+        .and(warp::get())
+        .and(with_state.clone())
+        .and_then(handle_health);
+
+    let metrics_route = warp::path!("metrics")
+        .and(warp::get())
+        .and(read_auth.clone())
+        .and(with_state.clone())
+        .and_then(handle_metrics);
+
+    let metrics_json_route = warp::path!("metrics-json")
+        .and(warp::get())
+        .and(read_auth.clone())
+        .and(with_state.clone())
+        .and_then(handle_metrics_json);
+
     let logs_route = warp::path!("logs")
         .and(warp::get())
+        .and(read_auth.clone())
         .and(warp::query::<LogQuery>())
         .and(with_state.clone())
         .and_then(handle_logs);
 
+    let clear_logs_route = warp::path!("logs" / "clear")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::body::bytes())
+        .and(with_state.clone())
+        .and_then(handle_clear_logs);
+
+    let history_route = warp::path!("history")
+        .and(warp::get())
+        .and(read_auth.clone())
+        .and(warp::query::<HistoryQuery>())
+        .and(with_state.clone())
+        .and_then(handle_history);
+
+    let logs_ws_route = warp::path!("logs" / "ws")
+        .and(read_auth.clone())
+        .and(warp::ws())
+        .and(with_state.clone())
+        .map(|ws: warp::ws::Ws, state: SharedState| {
+            let locked = state.lock();
+            let rx = locked.log_tx.subscribe();
+            let shutdown = locked.shutdown.clone();
+            ws.on_upgrade(move |socket| stream_logs(socket, rx, shutdown))
+        });
+
+    // The same feed as /logs/ws, for clients that would rather not speak WebSocket
+    let events_route = warp::path!("events")
+        .and(warp::get())
+        .and(read_auth.clone())
+        .and(with_state.clone())
+        .map(|state: SharedState| {
+            let locked = state.lock();
+            let events = log_events(locked.log_tx.subscribe(), locked.shutdown.clone());
+            sse::reply(sse::keep_alive().stream(events))
+        });
+
     let reload_route = warp::path!("reload")
         .and(warp::post())
+        .and(auth.clone())
         .and(with_state.clone())
         .and_then(handle_reload);
 
+    let remote_shutdown = CancellationToken::new();
+    let shutdown_route = warp::path!("shutdown")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::any().map(move || allow_remote_shutdown))
+        .and(warp::any().map({
+            let remote_shutdown = remote_shutdown.clone();
+            move || remote_shutdown.clone()
+        }))
+        .and(with_state.clone())
+        .and_then(handle_shutdown);
+
+    let version_route = warp::path!("version")
+        .and(warp::get())
+        .and(read_auth.clone())
+        .map(|| {
+            warp::reply::json(&serde_json::json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "git_sha": env!("NONAP_GIT_SHA"),
+                "build_time": env!("NONAP_BUILD_TIME"),
+            }))
+        });
+
+    let openapi_route = warp::path!("openapi.json")
+        .and(warp::get())
+        .and(read_auth.clone())
+        .map(|| warp::reply::json(&openapi_spec()));
+
     // Dashboard route (serves static html)
-    let dashboard_route = warp::path::end().and(warp::get()).map(|| {
-        warp::reply::html(DASHBOARD_HTML)
-    });
+    // Re-read on every request so edits show up without a restart
+    let dashboard_path = std::env::var("NONAP_DASHBOARD_PATH")
+        .ok()
+        .filter(|p| !p.is_empty());
+    let dashboard_route = warp::path::end()
+        .and(warp::get())
+        .and(read_auth.clone())
+        .and(warp::any().map(move || dashboard_path.clone()))
+        .and_then(handle_dashboard);
 
     // Combine all routes
     let routes = status_route
+        .or(status_history_route)
         .or(start_route)
         .or(stop_route)
+        .or(restart_route)
+        .or(pause_route)
+        .or(resume_route)
         .or(get_targets_route)
+        .or(config_route)
         .or(add_target_route)
+        .or(add_targets_route)
         .or(remove_target_route)
+        .or(delete_target_route)
+        .or(target_stats_route)
+        .or(reset_backoff_route)
+        .or(reset_circuit_route)
+        .or(toggle_target_route)
+        .or(update_target_route)
+        .or(ping_now_route)
+        .or(health_route)
+        .or(metrics_route)
+        .or(metrics_json_route)
         .or(logs_route)
+        .or(clear_logs_route)
+        .or(logs_ws_route)
+        .or(events_route)
+        .or(history_route)
         .or(reload_route)
+        .or(shutdown_route)
+        .or(version_route)
+        .or(openapi_route)
         .or(dashboard_route)
-        .with(warp::log("nonap"));
+        .recover(handle_rejection)
+        .map(|reply| Box::new(reply) as Box<dyn warp::Reply>);
 
-    warp::serve(routes).run(([0, 0, 0, 0], 3030)).await;
-}
+    // CORS stays off unless origins are configured, so same-origin behaviour is unchanged
+    let routes: BoxedFilter<(Box<dyn warp::Reply>,)> = match build_cors() {
+        Some(cors) => routes
+            .with(cors)
+            .map(|reply| Box::new(reply) as Box<dyn warp::Reply>)
+            .boxed(),
+        None => routes.boxed(),
+    };
+    let routes = routes.with(warp::log("nonap"));
 
-fn load_targets_from_file(path: &str) -> Result<Vec<PingTarget>, String> {
-    match fs::read_to_string(path) {
-        Ok(contents) => serde_json::from_str(&contents).map_err(|e| e.to_string()),
-        Err(e) => Err(format!("Failed to read {}: {}", path, e)),
-    }
-}
+    // Written once the listener is bound so an earlier startup failure leaves no stale file behind
+    let pidfile = std::env::var("NONAP_PIDFILE")
+        .ok()
+        .filter(|p| !p.is_empty());
 
-async fn ping_loop(target: PingTarget, client: Client, state: SharedState) {
-    loop {
-        {
-            let locked = state.lock();
-            if !locked.running {
-                break;
+    let addr = bind_address();
+    let shutdown_signal = {
+        let state = state.clone();
+        async move {
+            tokio::select! {
+                _ = wait_for_signal() => shutdown(state).await,
+                // POST /shutdown has already stopped the workers by the time this fires
+                _ = remote_shutdown.cancelled() => {}
             }
         }
+    };
+
+    // Report the bound address rather than the requested one, so port 0 shows the real port
+    match tls {
+        Some((cert, key)) => {
+            let (bound, server) = warp::serve(routes)
+                .tls()
+                .cert_path(cert)
+                .key_path(key)
+                .bind_with_graceful_shutdown(addr, shutdown_signal);
+            println!("🔒 Listening on https://{}", bound);
+            write_pidfile(pidfile.as_deref());
+            server.await;
+        }
+        None => {
+            let (bound, server) =
+                warp::serve(routes).bind_with_graceful_shutdown(addr, shutdown_signal);
+            println!("🌐 Listening on {}", bound);
+            write_pidfile(pidfile.as_deref());
+            server.await;
+        }
+    }
+    if let Some(path) = &pidfile {
+        if let Err(e) = fs::remove_file(path) {
+            eprintln!("⚠️ [NoNap] Failed to remove PID file {}: {}", path, e);
+        }
+    }
+    println!("👋 [NoNap] Shutting down cleanly");
+}
 
-        let delay = rand::thread_rng().gen_range(target.min_delay..=target.max_delay);
-        let msg = format!("🛌 [NoNap] Sleeping {} minutes before pinging {}", delay, &target.url);
-        println!("{}", msg);
-        append_log(state.clone(), msg);
+/// Writes our PID to `NONAP_PIDFILE`, exiting if that fails.
+fn write_pidfile(path: Option<&str>) {
+    let Some(path) = path else {
+        return;
+    };
+    if let Err(e) = fs::write(path, format!("{}\n", std::process::id())) {
+        eprintln!("❌ [NoNap] Failed to write PID file {}: {}", path, e);
+        std::process::exit(1);
+    }
+}
 
-        sleep(Duration::from_secs(delay * 60)).await;
+/// Builds the shared HTTP client.
+fn build_client() -> Result<Client, String> {
+    client_builder()?
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {}", e))
+}
 
-        match client.get(&target.url).send().await {
-            Ok(resp) => {
-                let status = resp.status();
-                let msg = format!("✅ [NoNap] {} responded with status {}", &target.url, status);
-                println!("{}", msg);
-                append_log(state.clone(), msg);
-            }
-            Err(e) => {
-                let msg = format!("❌ [NoNap] Failed to ping {}: {}", &target.url, e);
-                eprintln!("{}", msg);
-                append_log(state.clone(), msg);
+/// Client settings common to the shared client and per-target ones.
+/// `NONAP_HTTP_PROXY`/`NONAP_HTTPS_PROXY` take precedence over the standard
+/// proxy env vars, skipping hosts in `NONAP_NO_PROXY`. `NONAP_USER_AGENT`
+/// replaces the default User-Agent; a target's own header still wins.
+fn client_builder() -> Result<ClientBuilder, String> {
+    let no_proxy = std::env::var("NONAP_NO_PROXY")
+        .ok()
+        .and_then(|list| NoProxy::from_string(&list));
+    let mut builder = Client::builder();
+    for (var, https) in [("NONAP_HTTP_PROXY", false), ("NONAP_HTTPS_PROXY", true)] {
+        let Some(url) = std::env::var(var).ok().filter(|u| !u.is_empty()) else {
+            continue;
+        };
+        // The URL may carry credentials, so only the error is reported
+        let proxy = if https {
+            Proxy::https(url)
+        } else {
+            Proxy::http(url)
+        }
+        .map_err(|e| format!("Invalid {}: {}", var, e))?;
+        builder = builder.proxy(proxy.no_proxy(no_proxy.clone()));
+    }
+    if let Some(agent) = std::env::var("NONAP_USER_AGENT")
+        .ok()
+        .filter(|a| !a.is_empty())
+    {
+        let agent = HeaderValue::from_str(&agent)
+            .map_err(|e| format!("Invalid NONAP_USER_AGENT: {}", e))?;
+        builder = builder.user_agent(agent);
+    }
+    // Unset keeps reqwest's defaults: unlimited idle connections, 90s idle timeout
+    if let Some(max_idle) = env_parse_opt::<usize>("NONAP_POOL_MAX_IDLE") {
+        builder = builder.pool_max_idle_per_host(max_idle);
+    }
+    if let Some(secs) = env_parse_opt::<u64>("NONAP_POOL_IDLE_TIMEOUT") {
+        // 0 keeps idle connections until the server closes them
+        builder = builder.pool_idle_timeout((secs > 0).then(|| Duration::from_secs(secs)));
+    }
+    Ok(builder)
+}
+
+/// Resolves the targets file: `--config <path>` wins over `NONAP_CONFIG`,
+/// falling back to `targets.json`.
+fn config_path() -> String {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            if let Some(path) = args.next() {
+                return path;
             }
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            return path.to_string();
         }
     }
+    std::env::var("NONAP_CONFIG")
+        .ok()
+        .filter(|p| !p.is_empty())
+        .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string())
 }
 
-fn append_log(state: SharedState, message: String) {
-    // Add to in-memory logs
-    {
-        let mut locked = state.lock();
-        locked.logs.push(message.clone());
-        let len = locked.logs.len();
-        if len > 100 {
-            locked.logs.drain(..len - 100);
+/// Reads `NONAP_TLS_CERT`/`NONAP_TLS_KEY`. Both or neither must be set.
+fn tls_paths() -> Result<Option<(String, String)>, String> {
+    let cert = std::env::var("NONAP_TLS_CERT")
+        .ok()
+        .filter(|v| !v.is_empty());
+    let key = std::env::var("NONAP_TLS_KEY")
+        .ok()
+        .filter(|v| !v.is_empty());
+
+    match (cert, key) {
+        (None, None) => Ok(None),
+        (Some(cert), Some(key)) => {
+            for path in [&cert, &key] {
+                if !std::path::Path::new(path).is_file() {
+                    return Err(format!("TLS file {} does not exist", path));
+                }
+            }
+            Ok(Some((cert, key)))
         }
+        (Some(_), None) => Err("NONAP_TLS_CERT is set but NONAP_TLS_KEY is missing".to_string()),
+        (None, Some(_)) => Err("NONAP_TLS_KEY is set but NONAP_TLS_CERT is missing".to_string()),
     }
+}
 
-    // Append to log file (best effort, ignore errors)
-    let _ = std::thread::spawn(move || {
-        if let Ok(mut file) = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(LOG_FILE_PATH)
-        {
-            let _ = writeln!(file, "{}", message);
+async fn wait_for_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => {
+                sig.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
         }
-    });
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
 
-// -- Handlers --
+/// Stops all workers and flushes pending log writes before the server exits.
+async fn shutdown(state: SharedState) {
+    println!("🛑 [NoNap] Shutdown requested, stopping workers");
 
-async fn handle_status(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
-    let locked = state.lock();
-    let resp = serde_json::json!({
-        "running": locked.running,
-        "targets": locked.targets,
-        "logs_count": locked.logs.len()
-    });
-    Ok(warp::reply::json(&resp))
+    let handles = {
+        let mut locked = state.lock();
+        locked.running = false;
+        locked.cancel.cancel();
+        locked.shutdown.cancel();
+        locked.next_ping.clear();
+        std::mem::take(&mut locked.handles)
+    };
+    await_workers(handles).await;
+
+    persist_stats(&state);
+    flush_logs(&state).await;
 }
 
-async fn handle_start(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
-    let mut locked = state.lock();
+/// Waits until the log writer has written (and synced) every queued line.
+async fn flush_logs(state: &SharedState) {
+    let (tx, rx) = oneshot::channel();
+    if state.lock().log_writer.send(LogCommand::Flush(tx)).is_ok() {
+        let _ = tokio::time::timeout(LOG_FLUSH_TIMEOUT, rx).await;
+    }
+}
 
-    if locked.running {
-        return Ok(warp::reply::with_status(
-            "Already running",
-            StatusCode::BAD_REQUEST,
-        ));
+fn bind_address() -> SocketAddr {
+    let default: SocketAddr = DEFAULT_BIND.parse().expect("default bind address is valid");
+    match std::env::var("NONAP_BIND") {
+        Ok(value) => match value.parse() {
+            Ok(addr) => addr,
+            Err(e) => {
+                eprintln!(
+                    "⚠️ [NoNap] Invalid NONAP_BIND {:?} ({}), falling back to {}",
+                    value, e, default
+                );
+                default
+            }
+        },
+        Err(_) => default,
     }
+}
 
-    locked.running = true;
+/// Parses an env var, warning and falling back to `default` when it is invalid.
+fn env_parse<T>(name: &str, default: T) -> T
+where
+    T: std::str::FromStr + std::fmt::Display,
+    T::Err: std::fmt::Display,
+{
+    match std::env::var(name) {
+        Ok(value) => match value.trim().parse() {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                eprintln!(
+                    "⚠️ [NoNap] Invalid {} {:?} ({}), falling back to {}",
+                    name, value, e, default
+                );
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+/// Like `env_parse`, but for settings that are off unless set.
+fn env_parse_opt<T>(name: &str) -> Option<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = std::env::var(name).ok().filter(|v| !v.trim().is_empty())?;
+    match value.trim().parse() {
+        Ok(parsed) => Some(parsed),
+        Err(e) => {
+            eprintln!(
+                "⚠️ [NoNap] Invalid {} {:?} ({}), ignoring it",
+                name, value, e
+            );
+            None
+        }
+    }
+}
 
-    let client = Client::new();
-    locked.handles = vec![];
+fn env_flag(name: &str) -> bool {
+    env_flag_or(name, false)
+}
 
-    for target in locked.targets.clone() {
-        let c = client.clone();
-        let s = state.clone();
-        let handle = tokio::spawn(async move { ping_loop(target, c, s).await });
-        locked.handles.push(handle);
+/// Like `env_flag`, for flags that default to on; only an explicit "0",
+/// "false" or "no" turns them off.
+fn env_flag_or(name: &str, default: bool) -> bool {
+    match std::env::var(name)
+        .map(|v| v.to_ascii_lowercase())
+        .as_deref()
+    {
+        Ok("1") | Ok("true") | Ok("yes") => true,
+        Ok("0") | Ok("false") | Ok("no") => false,
+        _ => default,
     }
+}
 
-    Ok(warp::reply::with_status("Started pinging", StatusCode::OK))
+#[derive(Debug)]
+struct Unauthorized {
+    /// `WWW-Authenticate` value; `Basic` makes browsers prompt for credentials
+    challenge: &'static str,
 }
 
-async fn handle_stop(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
-    let mut locked = state.lock();
+impl warp::reject::Reject for Unauthorized {}
 
-    if !locked.running {
-        return Ok(warp::reply::with_status(
-            "Already stopped",
-            StatusCode::BAD_REQUEST,
-        ));
-    }
+/// Credentials a route accepts; with neither set the route is open.
+#[derive(Clone)]
+struct AuthConfig {
+    token: Option<String>,
+    /// Base64 of `user:pass`, as it appears in an `Authorization: Basic` header
+    basic: Option<String>,
+}
 
-    locked.running = false;
-    locked.handles = vec![];
+/// Reads `NONAP_BASIC_USER`/`NONAP_BASIC_PASS`; both must be set to enable Basic auth.
+fn basic_credentials() -> Option<String> {
+    let user = std::env::var("NONAP_BASIC_USER")
+        .ok()
+        .filter(|u| !u.is_empty());
+    let pass = std::env::var("NONAP_BASIC_PASS")
+        .ok()
+        .filter(|p| !p.is_empty());
+    match (user, pass) {
+        (Some(user), Some(pass)) => {
+            Some(base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, pass)))
+        }
+        (None, None) => None,
+        _ => {
+            eprintln!(
+                "⚠️ [NoNap] Basic auth needs both NONAP_BASIC_USER and NONAP_BASIC_PASS; ignoring"
+            );
+            None
+        }
+    }
+}
 
-    Ok(warp::reply::with_status("Stopped pinging", StatusCode::OK))
+/// Rejects requests whose `Authorization` header matches neither the bearer token
+/// nor the Basic credentials configured in `auth`. Passing neither makes the filter
+/// a no-op.
+fn require_auth(auth: AuthConfig) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let auth = auth.clone();
+            async move {
+                if auth.token.is_none() && auth.basic.is_none() {
+                    return Ok(());
+                }
+                let header = header.as_deref().unwrap_or("");
+                let matches = |scheme: &str, expected: &Option<String>| {
+                    expected.as_ref().is_some_and(|expected| {
+                        let provided = header.strip_prefix(scheme).unwrap_or("");
+                        constant_time_eq(provided.as_bytes(), expected.as_bytes())
+                    })
+                };
+                if matches("Bearer ", &auth.token) || matches("Basic ", &auth.basic) {
+                    Ok(())
+                } else {
+                    let challenge = if auth.basic.is_some() {
+                        "Basic realm=\"NoNap\""
+                    } else {
+                        "Bearer"
+                    };
+                    Err(warp::reject::custom(Unauthorized { challenge }))
+                }
+            }
+        })
+        .untuple_one()
 }
 
-async fn handle_get_targets(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
-    let locked = state.lock();
-    Ok(warp::reply::json(&locked.targets))
+#[derive(Debug)]
+struct RateLimited {
+    retry_after_secs: u64,
 }
 
-async fn handle_add_target(
-    new_target: PingTarget,
-    state: SharedState,
-) -> Result<impl warp::Reply, warp::Rejection> {
-    let mut locked = state.lock();
+impl warp::reject::Reject for RateLimited {}
 
-    if locked.targets.iter().any(|t| t.url == new_target.url) {
-        return Ok(warp::reply::with_status(
-            "Target already exists",
-            StatusCode::BAD_REQUEST,
-        ));
+/// Per-IP token buckets holding `per_minute` requests, refilled continuously.
+struct RateLimiter {
+    per_minute: u32,
+    buckets: Mutex<HashMap<IpAddr, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    fn new(per_minute: u32) -> Option<Arc<Self>> {
+        (per_minute > 0).then(|| {
+            Arc::new(RateLimiter {
+                per_minute,
+                buckets: Mutex::new(HashMap::new()),
+            })
+        })
     }
 
-    locked.targets.push(new_target);
+    /// Takes a token for `ip`, or returns how many seconds until one is available.
+    fn check(&self, ip: IpAddr) -> Result<(), u64> {
+        let capacity = f64::from(self.per_minute);
+        let per_sec = capacity / 60.0;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock();
 
-    if locked.running {
-        locked.running = false;
-        locked.handles = vec![];
-        locked.running = true;
+        // Buckets that have refilled completely carry no state worth keeping
+        if buckets.len() >= RATE_LIMIT_MAX_CLIENTS {
+            buckets.retain(|_, (tokens, last)| {
+                *tokens + last.elapsed().as_secs_f64() * per_sec < capacity
+            });
+        }
 
-        let client = Client::new();
-        for target in locked.targets.clone() {
-            let c = client.clone();
-            let s = state.clone();
-            let handle = tokio::spawn(async move { ping_loop(target, c, s).await });
-            locked.handles.push(handle);
+        let (tokens, last) = buckets.entry(ip).or_insert((capacity, now));
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * per_sec).min(capacity);
+        *last = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - *tokens) / per_sec).ceil() as u64)
         }
     }
+}
 
-    Ok(warp::reply::with_status("Target added", StatusCode::OK))
+/// Rejects clients that exceed the limiter's rate. Passing `None` makes the
+/// filter a no-op.
+fn rate_limit(
+    limiter: Option<Arc<RateLimiter>>,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::addr::remote()
+        .and_then(move |addr: Option<SocketAddr>| {
+            let limiter = limiter.clone();
+            async move {
+                match (limiter, addr) {
+                    (Some(limiter), Some(addr)) => {
+                        limiter.check(addr.ip()).map_err(|retry_after_secs| {
+                            warp::reject::custom(RateLimited { retry_after_secs })
+                        })
+                    }
+                    _ => Ok(()),
+                }
+            }
+        })
+        .untuple_one()
 }
 
-#[derive(Deserialize)]
-struct RemoveTargetBody {
-    url: String,
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
 }
 
-async fn handle_remove_target(
-    body: RemoveTargetBody,
-    state: SharedState,
-) -> Result<impl warp::Reply, warp::Rejection> {
+async fn handle_rejection(err: warp::Rejection) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    if let Some(unauthorized) = err.find::<Unauthorized>() {
+        return Ok(Box::new(warp::reply::with_header(
+            api_reply(StatusCode::UNAUTHORIZED, "Unauthorized"),
+            "WWW-Authenticate",
+            unauthorized.challenge,
+        )));
+    }
+    if let Some(limited) = err.find::<RateLimited>() {
+        return Ok(Box::new(warp::reply::with_header(
+            api_reply(StatusCode::TOO_MANY_REQUESTS, "Too many requests"),
+            "Retry-After",
+            limited.retry_after_secs.to_string(),
+        )));
+    }
+    Err(err)
+}
+
+/// Builds the CORS policy from `NONAP_CORS_ORIGINS` (comma-separated origins or `*`).
+fn build_cors() -> Option<warp::cors::Builder> {
+    let value = std::env::var("NONAP_CORS_ORIGINS").ok()?;
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+
+    let cors = warp::cors()
+        .allow_methods(vec!["GET", "POST", "DELETE", "OPTIONS"])
+        .allow_headers(vec!["content-type", "authorization"]);
+
+    if value == "*" {
+        return Some(cors.allow_any_origin());
+    }
+
+    // warp panics on malformed origins, so normalize and drop bad entries first
+    let mut origins = Vec::new();
+    for origin in value.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+        match reqwest::Url::parse(origin) {
+            Ok(url) if url.has_host() => origins.push(url.origin().ascii_serialization()),
+            _ => eprintln!("⚠️ [NoNap] Ignoring invalid CORS origin {:?}", origin),
+        }
+    }
+    if origins.is_empty() {
+        return None;
+    }
+    Some(cors.allow_origins(origins.iter().map(String::as_str)))
+}
+
+/// Why a targets file couldn't be loaded. A missing file is a normal first run,
+/// while a malformed one is usually a typo that shouldn't go unnoticed.
+#[derive(Debug)]
+enum ConfigError {
+    Missing(String),
+    Unreadable(String, std::io::Error),
+    /// The parser's message includes the line and column
+    Malformed(String, String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Missing(path) => write!(f, "{} not found", path),
+            ConfigError::Unreadable(path, e) => write!(f, "Failed to read {}: {}", path, e),
+            ConfigError::Malformed(path, e) => write!(f, "{} is malformed: {}", path, e),
+        }
+    }
+}
+
+/// Loads the targets file, or every targets file in `path` if it's a directory.
+/// Also returns how many entries were skipped, since persisting the loaded
+/// list would then drop them from the file.
+fn load_targets_from_file(path: &str) -> Result<(Vec<PingTarget>, usize), ConfigError> {
+    let mut seen: HashMap<String, String> = HashMap::new();
+    let mut targets = Vec::new();
+    let mut skipped = 0;
+    for (file, t) in read_targets(path)? {
+        // Skip invalid targets instead of rejecting the whole file
+        if let Err(e) = validate_target(&t) {
+            eprintln!(
+                "⚠️ [NoNap] Skipping invalid target {} from {}: {}",
+                t.url, file, e
+            );
+            skipped += 1;
+            continue;
+        }
+        // Keep the first entry for a URL, like /add-target rejecting duplicates
+        if let Some(first) = seen.get(&t.url) {
+            eprintln!(
+                "⚠️ [NoNap] Skipping duplicate target {} from {} (already defined in {})",
+                t.url, file, first
+            );
+            skipped += 1;
+            continue;
+        }
+        seen.insert(t.url.clone(), file);
+        targets.push(t);
+    }
+    Ok((targets, skipped))
+}
+
+/// Why saving would lose part of the config, for `AppState::persist_blocked`.
+fn lossy_load_reason(skipped: usize) -> Option<String> {
+    (skipped > 0).then(|| format!("had {} skipped target(s) when last loaded", skipped))
+}
+
+/// Parses the config without validating the individual targets, pairing each
+/// with the file it came from. A directory is read file by file in name
+/// order, so the earlier file wins when two define the same URL.
+fn read_targets(path: &str) -> Result<Vec<(String, PingTarget)>, ConfigError> {
+    if !Path::new(path).is_dir() {
+        let targets = read_targets_file(path)?;
+        return Ok(targets.into_iter().map(|t| (path.to_string(), t)).collect());
+    }
+
+    let entries = fs::read_dir(path).map_err(|e| ConfigError::Unreadable(path.to_string(), e))?;
+    let mut files: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|file| file.is_file() && is_targets_file(file))
+        .map(|file| file.to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+
+    let mut targets = Vec::new();
+    for file in files {
+        let parsed = read_targets_file(&file)?;
+        targets.extend(parsed.into_iter().map(|t| (file.clone(), t)));
+    }
+    Ok(targets)
+}
+
+/// Files picked up from a config directory: JSON and YAML, skipping hidden
+/// ones such as editor swap files.
+fn is_targets_file(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with('.'));
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    !hidden
+        && ["json", "yaml", "yml"]
+            .iter()
+            .any(|e| ext.eq_ignore_ascii_case(e))
+}
+
+/// Parses a targets file without validating the individual targets.
+fn read_targets_file(path: &str) -> Result<Vec<PingTarget>, ConfigError> {
+    let contents = fs::read_to_string(path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => ConfigError::Missing(path.to_string()),
+        _ => ConfigError::Unreadable(path.to_string(), e),
+    })?;
+    let parsed = if is_yaml_path(path) {
+        serde_yaml::from_str(&contents).map_err(|e| e.to_string())
+    } else {
+        serde_json::from_str(&contents).map_err(|e| e.to_string())
+    };
+    parsed.map_err(|e| ConfigError::Malformed(path.to_string(), e))
+}
+
+/// `--check`: validates every target in the config and reports each problem,
+/// returning whether the whole file is valid.
+fn check_config(path: &str) -> bool {
+    println!("🔍 [NoNap] Checking {}", path);
+    let targets: Vec<PingTarget> = match read_targets(path) {
+        Ok(targets) => targets.into_iter().map(|(_, t)| t).collect(),
+        Err(e) => {
+            eprintln!("❌ [NoNap] {}", e);
+            return false;
+        }
+    };
+
+    let mut invalid = 0;
+    let mut seen = HashSet::new();
+    for target in &targets {
+        let mut problems: Vec<String> = validate_target(target).err().into_iter().collect();
+        if !seen.insert(target.url.as_str()) {
+            problems.push("duplicate url, only the first entry is used".to_string());
+        }
+        // Invalid headers are only skipped at runtime, but worth flagging here
+        // (unset variables are already reported by validate_target)
+        for (name, value) in target.headers.iter().flatten() {
+            let Ok(value) = expand_env(value) else {
+                continue;
+            };
+            if let Err(e) = parse_header(name, &value) {
+                problems.push(format!("invalid header {:?}: {}", name, e));
+            }
+        }
+        for problem in &problems {
+            eprintln!("❌ [NoNap] {}: {}", target.url, problem);
+        }
+        if !problems.is_empty() {
+            invalid += 1;
+        }
+    }
+
+    if invalid == 0 {
+        println!("✅ [NoNap] {} targets OK", targets.len());
+        true
+    } else {
+        eprintln!(
+            "❌ [NoNap] {} of {} targets invalid",
+            invalid,
+            targets.len()
+        );
+        false
+    }
+}
+
+fn validate_target(target: &PingTarget) -> Result<(), String> {
+    let url = reqwest::Url::parse(&expand_env(&target.url)?)
+        .map_err(|e| format!("invalid url: {}", e))?;
+    match parse_check_type(target.check_type.as_deref())? {
+        CheckType::Http => {
+            if !matches!(url.scheme(), "http" | "https") {
+                return Err(format!(
+                    "unsupported url scheme {:?}, expected http or https",
+                    url.scheme()
+                ));
+            }
+        }
+        CheckType::Tcp => {
+            tcp_address(&url)?;
+        }
+        CheckType::Dns => {
+            dns_host(&url)?;
+        }
+    }
+    parse_ip_family(target.ip_family.as_deref())?;
+    let uses_range = target.min_delay.is_some() || target.max_delay.is_some();
+    let uses_jitter = target.base_delay.is_some() || target.jitter_pct.is_some();
+    let schemes = [target.interval.is_some(), uses_range, uses_jitter];
+    match schemes.iter().filter(|&&used| used).count() {
+        0 => {
+            return Err(
+                "one of interval, min_delay/max_delay or base_delay is required".to_string(),
+            );
+        }
+        1 => {}
+        _ => {
+            return Err(
+                "set only one of interval, min_delay/max_delay or base_delay/jitter_pct"
+                    .to_string(),
+            );
+        }
+    }
+    if target.concurrency == Some(0) {
+        return Err("concurrency must be greater than 0".to_string());
+    }
+    if target
+        .concurrency
+        .is_some_and(|c| c > MAX_TARGET_CONCURRENCY)
+    {
+        return Err(format!(
+            "concurrency must be at most {}",
+            MAX_TARGET_CONCURRENCY
+        ));
+    }
+    if target.max_pings == Some(0) {
+        return Err("max_pings must be greater than 0".to_string());
+    }
+    if target.backoff_after == Some(0) {
+        return Err("backoff_after must be greater than 0".to_string());
+    }
+    if target.warmup_pings == Some(0) {
+        return Err("warmup_pings must be greater than 0".to_string());
+    }
+    if let Some(breaker) = &target.circuit_breaker {
+        if breaker.failures == 0 {
+            return Err("circuit_breaker failures must be greater than 0".to_string());
+        }
+        if breaker.reset_timeout_secs == Some(0) {
+            return Err("circuit_breaker reset_timeout_secs must be greater than 0".to_string());
+        }
+    }
+    if target.interval == Some(0) {
+        return Err("interval must be greater than 0".to_string());
+    }
+    if uses_range {
+        match (target.min_delay, target.max_delay) {
+            (Some(min), Some(max)) if min > max => {
+                return Err(format!(
+                    "min_delay ({}) must not be greater than max_delay ({})",
+                    min, max
+                ));
+            }
+            (Some(_), Some(_)) => {}
+            _ => return Err("both min_delay and max_delay are required".to_string()),
+        }
+    }
+    if uses_jitter {
+        match (target.base_delay, target.jitter_pct) {
+            (None, _) => return Err("jitter_pct requires base_delay".to_string()),
+            (Some(0), _) => return Err("base_delay must be greater than 0".to_string()),
+            (_, Some(pct)) if pct > 100 => {
+                return Err(format!("jitter_pct ({}) must be at most 100", pct));
+            }
+            _ => {}
+        }
+    }
+    for (name, value) in target.headers.iter().flatten() {
+        expand_env(value).map_err(|e| format!("header {:?}: {}", name, e))?;
+    }
+    basic_auth_credentials(target)?;
+    HmacSigner::from_target(target)?;
+    if target.max_response_bytes == Some(0) {
+        return Err("max_response_bytes must be greater than 0".to_string());
+    }
+    if target.down_after == Some(0) {
+        return Err("down_after must be greater than 0".to_string());
+    }
+    delay_unit(target.unit.as_deref())?;
+    parse_method(target.method.as_deref())?;
+    if target.expected_status.is_some() && target.expected_range.is_some() {
+        return Err("set only one of expected_status or expected_range".to_string());
+    }
+    if let Some(range) = &target.expected_range {
+        parse_status_range(range)?;
+    }
+    BodyMatcher::from_target(target)?;
+    QuietHours::from_target(target)?;
+    if target.tags.iter().flatten().any(|t| t.trim().is_empty()) {
+        return Err("tags must not be empty".to_string());
+    }
+    Ok(())
+}
+
+/// What a healthy response body has to contain. Checked only once the
+/// status is as expected.
+#[derive(Debug, Clone)]
+enum BodyMatcher {
+    Contains(String),
+    Regex(Regex),
+}
+
+impl BodyMatcher {
+    fn from_target(target: &PingTarget) -> Result<Option<Self>, String> {
+        match (&target.body_contains, &target.body_regex) {
+            (Some(_), Some(_)) => Err("set only one of body_contains or body_regex".to_string()),
+            (Some(needle), None) => Ok(Some(BodyMatcher::Contains(needle.clone()))),
+            (None, Some(pattern)) => Regex::new(pattern)
+                .map(|re| Some(BodyMatcher::Regex(re)))
+                .map_err(|e| format!("invalid body_regex: {}", e)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    fn matches(&self, body: &str) -> bool {
+        match self {
+            BodyMatcher::Contains(needle) => body.contains(needle.as_str()),
+            BodyMatcher::Regex(re) => re.is_match(body),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            BodyMatcher::Contains(needle) => format!("to contain {:?}", needle),
+            BodyMatcher::Regex(re) => format!("to match /{}/", re.as_str()),
+        }
+    }
+}
+
+/// A parsed `quiet_hours` window in the target's timezone.
+#[derive(Debug, Clone, Copy)]
+struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+    tz: Tz,
+}
+
+impl QuietHours {
+    fn from_target(target: &PingTarget) -> Result<Option<Self>, String> {
+        let tz = match &target.timezone {
+            Some(name) => name
+                .parse::<Tz>()
+                .map_err(|_| format!("unknown timezone {:?}", name))?,
+            None => Tz::UTC,
+        };
+        let Some((start, end)) = &target.quiet_hours else {
+            return Ok(None);
+        };
+        let parse = |t: &str| {
+            NaiveTime::parse_from_str(t, "%H:%M")
+                .map_err(|_| format!("invalid quiet_hours time {:?}, expected HH:MM", t))
+        };
+        let (start, end) = (parse(start)?, parse(end)?);
+        if start == end {
+            return Err("quiet_hours start and end must differ".to_string());
+        }
+        Ok(Some(QuietHours { start, end, tz }))
+    }
+
+    fn contains(&self, now: DateTime<Utc>) -> bool {
+        let time = now.with_timezone(&self.tz).time();
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            // Crosses midnight, e.g. 22:00-06:00
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// Expands `${VAR}` placeholders from the process environment, so secrets can
+/// stay out of the targets file. Targets keep the unexpanded form, which is
+/// what gets persisted and shown by the API.
+fn expand_env(value: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start + 2..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + 2 + len];
+        let expanded =
+            std::env::var(name).map_err(|_| format!("environment variable {} is not set", name))?;
+        out.push_str(&rest[..start]);
+        out.push_str(&expanded);
+        rest = &rest[start + 3 + len..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Parses a status class like "2xx" into its leading digit.
+fn parse_status_range(range: &str) -> Result<u16, String> {
+    let bytes = range.as_bytes();
+    if bytes.len() == 3
+        && range[1..].eq_ignore_ascii_case("xx")
+        && (b'1'..=b'5').contains(&bytes[0])
+    {
+        Ok(u16::from(bytes[0] - b'0'))
+    } else {
+        Err(format!(
+            "invalid expected_range {:?}, expected e.g. \"2xx\"",
+            range
+        ))
+    }
+}
+
+fn status_is_expected(target: &PingTarget, status: u16) -> bool {
+    if let Some(expected) = target.expected_status {
+        return status == expected;
+    }
+    if let Some(class) = target
+        .expected_range
+        .as_deref()
+        .and_then(|r| parse_status_range(r).ok())
+    {
+        return status / 100 == class;
+    }
+    (200..400).contains(&status)
+}
+
+fn describe_expected(target: &PingTarget) -> String {
+    match (&target.expected_status, &target.expected_range) {
+        (Some(code), _) => code.to_string(),
+        (None, Some(range)) => range.clone(),
+        (None, None) => "2xx/3xx".to_string(),
+    }
+}
+
+/// Targets files ending in `.yaml`/`.yml` are read and written as YAML; anything else is JSON.
+fn is_yaml_path(path: &str) -> bool {
+    matches!(
+        Path::new(path).extension().and_then(|e| e.to_str()),
+        Some(ext) if ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml")
+    )
+}
+
+/// Writes JSON atomically: a temp file is written first and then renamed over
+/// the original so a crash never leaves a half-written file.
+fn write_json_atomic<T: Serialize + ?Sized>(path: &str, value: &T) -> Result<(), String> {
+    let contents = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+    write_atomic(path, contents)
+}
+
+/// Rewrites the whole file; serde_yaml doesn't keep a YAML file's comments.
+fn write_targets_atomic(path: &str, targets: &[PingTarget]) -> Result<(), String> {
+    if is_yaml_path(path) {
+        let contents = serde_yaml::to_string(targets).map_err(|e| e.to_string())?;
+        write_atomic(path, contents)
+    } else {
+        write_json_atomic(path, targets)
+    }
+}
+
+fn write_atomic(path: &str, contents: String) -> Result<(), String> {
+    let tmp_path = format!("{}.tmp", path);
+    fs::write(&tmp_path, contents).map_err(|e| format!("Failed to write {}: {}", tmp_path, e))?;
+    fs::rename(&tmp_path, path).map_err(|e| format!("Failed to replace {}: {}", path, e))
+}
+
+/// Loads saved stats, keeping only entries for currently configured targets.
+fn load_stats_from_file(path: &str, targets: &[PingTarget]) -> HashMap<String, TargetStats> {
+    let stats: HashMap<String, TargetStats> = match fs::read_to_string(path) {
+        Ok(contents) => match serde_json::from_str(&contents) {
+            Ok(stats) => stats,
+            Err(e) => {
+                eprintln!("⚠️ [NoNap] Ignoring unreadable {}: {}", path, e);
+                return HashMap::new();
+            }
+        },
+        Err(_) => return HashMap::new(),
+    };
+
+    stats
+        .into_iter()
+        .filter(|(url, _)| targets.iter().any(|t| &t.url == url))
+        .collect()
+}
+
+fn persist_stats(state: &SharedState) {
+    let stats = state.lock().stats.clone();
+    if let Err(e) = write_json_atomic(STATS_FILE_PATH, &stats) {
+        let msg = format!("❌ [NoNap] Failed to persist stats: {}", e);
+        eprint_styled(Style::Failure, &msg);
+        append_log(state.clone(), LogLevel::Error, msg);
+    }
+}
+
+/// Periodically saves stats until shutdown, which does a final save itself.
+async fn stats_saver(state: SharedState) {
+    let shutdown = state.lock().shutdown.clone();
+    let mut ticker = tokio::time::interval(STATS_SAVE_INTERVAL);
+    ticker.tick().await;
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = ticker.tick() => persist_stats(&state),
+        }
+    }
+}
+
+fn persist_targets(state: &SharedState, targets: &[PingTarget]) {
+    let (path, blocked) = {
+        let locked = state.lock();
+        (locked.config_path.clone(), locked.persist_blocked.clone())
+    };
+    if let Some(reason) = blocked {
+        let msg = format!(
+            "⚠️ [NoNap] Not saving targets: {} {}, so API changes last until it reloads cleanly",
+            path, reason
+        );
+        eprint_styled(Style::Warning, &msg);
+        append_log(state.clone(), LogLevel::Warn, msg);
+        return;
+    }
+    // There's no telling which of a directory's files a change belongs in
+    if Path::new(&path).is_dir() {
+        let msg = format!(
+            "⚠️ [NoNap] Not saving targets: {} is a directory, so API changes last until the next reload or restart",
+            path
+        );
+        eprint_styled(Style::Warning, &msg);
+        append_log(state.clone(), LogLevel::Warn, msg);
+        return;
+    }
+    if let Err(e) = write_targets_atomic(&path, targets) {
+        let msg = format!("❌ [NoNap] Failed to persist targets: {}", e);
+        eprint_styled(Style::Failure, &msg);
+        append_log(state.clone(), LogLevel::Error, msg);
+    }
+}
+
+fn parse_method(method: Option<&str>) -> Result<Method, String> {
+    match method.map(|m| m.to_ascii_uppercase()).as_deref() {
+        None | Some("GET") => Ok(Method::GET),
+        Some("HEAD") => Ok(Method::HEAD),
+        Some("POST") => Ok(Method::POST),
+        Some("PUT") => Ok(Method::PUT),
+        Some("PATCH") => Ok(Method::PATCH),
+        Some("DELETE") => Ok(Method::DELETE),
+        Some("OPTIONS") => Ok(Method::OPTIONS),
+        Some(other) => Err(format!("unsupported method {}", other)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CheckType {
+    Http,
+    Tcp,
+    Dns,
+}
+
+fn parse_check_type(check_type: Option<&str>) -> Result<CheckType, String> {
+    match check_type.map(|c| c.to_ascii_lowercase()).as_deref() {
+        None | Some("http") => Ok(CheckType::Http),
+        Some("tcp") => Ok(CheckType::Tcp),
+        Some("dns") => Ok(CheckType::Dns),
+        Some(other) => Err(format!(
+            "unsupported check_type {:?}, expected http, tcp or dns",
+            other
+        )),
+    }
+}
+
+/// `host:port` for a TCP check; the port may be implied by an http(s) scheme.
+fn tcp_address(url: &reqwest::Url) -> Result<String, String> {
+    match (url.host_str(), url.port_or_known_default()) {
+        (Some(host), Some(port)) => Ok(format!("{}:{}", host, port)),
+        _ => Err("tcp check needs a host and port, e.g. tcp://db.internal:5432".to_string()),
+    }
+}
+
+fn dns_host(url: &reqwest::Url) -> Result<String, String> {
+    url.host_str()
+        .map(str::to_string)
+        .ok_or_else(|| "dns check needs a host, e.g. dns://example.com".to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    fn matches(self, ip: IpAddr) -> bool {
+        match self {
+            IpFamily::V4 => ip.is_ipv4(),
+            IpFamily::V6 => ip.is_ipv6(),
+        }
+    }
+
+    /// Binding to this makes the connector skip addresses of the other family.
+    fn unspecified(self) -> IpAddr {
+        match self {
+            IpFamily::V4 => IpAddr::from([0u8; 4]),
+            IpFamily::V6 => IpAddr::from([0u16; 8]),
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            IpFamily::V4 => "IPv4",
+            IpFamily::V6 => "IPv6",
+        }
+    }
+}
+
+fn parse_ip_family(ip_family: Option<&str>) -> Result<Option<IpFamily>, String> {
+    match ip_family.map(|f| f.to_ascii_lowercase()).as_deref() {
+        None => Ok(None),
+        Some("v4") => Ok(Some(IpFamily::V4)),
+        Some("v6") => Ok(Some(IpFamily::V6)),
+        Some(other) => Err(format!(
+            "unsupported ip_family {:?}, expected v4 or v6",
+            other
+        )),
+    }
+}
+
+/// " over IPv4"/" over IPv6" for log lines of targets pinned to a family, so the
+/// log shows the family really used.
+fn family_note(family: Option<IpFamily>, addr: Option<SocketAddr>) -> String {
+    match (family, addr) {
+        (Some(_), Some(addr)) => {
+            let used = if addr.is_ipv4() {
+                IpFamily::V4
+            } else {
+                IpFamily::V6
+            };
+            format!(" over {}", used.label())
+        }
+        _ => String::new(),
+    }
+}
+
+/// A check that doesn't send an HTTP request.
+#[derive(Debug, Clone)]
+enum Probe {
+    /// Connect to `host:port`
+    Tcp(String),
+    /// Resolve the host
+    Dns(String),
+}
+
+fn parse_header(name: &str, value: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| e.to_string())?;
+    let value = HeaderValue::from_str(value).map_err(|e| e.to_string())?;
+    Ok((name, value))
+}
+
+/// Expands the target's `basic_auth`. Errors name the field, never the values.
+fn basic_auth_credentials(target: &PingTarget) -> Result<Option<(String, String)>, String> {
+    let Some((user, password)) = &target.basic_auth else {
+        return Ok(None);
+    };
+    let user = expand_env(user).map_err(|e| format!("basic_auth user: {}", e))?;
+    let password = expand_env(password).map_err(|e| format!("basic_auth password: {}", e))?;
+    Ok(Some((user, password)))
+}
+
+const DEFAULT_HMAC_TIMESTAMP_HEADER: &str = "X-Timestamp";
+
+/// A target's resolved `hmac` settings; the signature itself changes per request.
+#[derive(Clone)]
+struct HmacSigner {
+    secret: Vec<u8>,
+    header: HeaderName,
+    timestamp_header: HeaderName,
+}
+
+// Keeps the secret out of `RequestSpec`'s debug output
+impl std::fmt::Debug for HmacSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HmacSigner")
+            .field("header", &self.header)
+            .field("timestamp_header", &self.timestamp_header)
+            .finish_non_exhaustive()
+    }
+}
+
+impl HmacSigner {
+    /// Resolves the target's `hmac` config. Errors name the variable, never the secret.
+    fn from_target(target: &PingTarget) -> Result<Option<Self>, String> {
+        let Some(config) = &target.hmac else {
+            return Ok(None);
+        };
+        let secret = std::env::var(&config.secret_env)
+            .ok()
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| format!("hmac secret_env {} is not set", config.secret_env))?;
+        let header_name = |name: &str| {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| format!("invalid hmac header {:?}: {}", name, e))
+        };
+        Ok(Some(HmacSigner {
+            secret: secret.into_bytes(),
+            header: header_name(&config.header_name)?,
+            timestamp_header: header_name(
+                config
+                    .timestamp_header
+                    .as_deref()
+                    .unwrap_or(DEFAULT_HMAC_TIMESTAMP_HEADER),
+            )?,
+        }))
+    }
+
+    /// Returns the timestamp and its hex-encoded signature.
+    fn sign(&self, timestamp: i64) -> (String, String) {
+        let timestamp = timestamp.to_string();
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(timestamp.as_bytes());
+        let signature = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        (timestamp, signature)
+    }
+}
+
+/// Per-target request settings resolved once rather than on every ping.
+#[derive(Debug, Clone)]
+struct RequestSpec {
+    /// Target URL with `${VAR}` placeholders expanded
+    url: String,
+    method: Method,
+    headers: HeaderMap,
+    /// Bytes of a failing response body to keep for the log, if capturing
+    failure_body_limit: Option<usize>,
+    /// Compiled once per loop rather than per request
+    body_matcher: Option<BodyMatcher>,
+    /// Dedicated client for targets whose settings the shared one can't honor
+    client: Option<Client>,
+    /// Payload to send, if the method takes one
+    body: Option<String>,
+    /// Expanded `basic_auth`; never logged
+    basic_auth: Option<(String, String)>,
+    /// Replaces the HTTP request for "tcp" and "dns" checks
+    probe: Option<Probe>,
+    ip_family: Option<IpFamily>,
+    hmac: Option<HmacSigner>,
+}
+
+/// Resolves a target's method and headers. Invalid headers are logged and
+/// skipped; an unsupported method is an error.
+fn prepare_request(target: &PingTarget, state: &SharedState) -> Result<RequestSpec, String> {
+    let method = parse_method(target.method.as_deref())?;
+
+    let url = expand_env(&target.url)?;
+    let parsed = || reqwest::Url::parse(&url).map_err(|e| format!("invalid url: {}", e));
+    let probe = match parse_check_type(target.check_type.as_deref())? {
+        CheckType::Http => None,
+        CheckType::Tcp => Some(Probe::Tcp(tcp_address(&parsed()?)?)),
+        CheckType::Dns => Some(Probe::Dns(dns_host(&parsed()?)?)),
+    };
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in target.headers.iter().flatten() {
+        let value = expand_env(value).map_err(|e| format!("header {:?}: {}", name, e))?;
+        match parse_header(name, &value) {
+            Ok((n, v)) => {
+                headers.insert(n, v);
+            }
+            Err(e) => {
+                // The value may be a secret, so only the name is logged
+                let msg = format!(
+                    "⚠️ [NoNap] Skipping invalid header {:?} for {}: {}",
+                    name, &target.url, e
+                );
+                eprint_styled(Style::Warning, &msg);
+                append_target_log(state.clone(), &target.url, LogLevel::Warn, msg);
+            }
+        }
+    }
+
+    let body = match &target.body {
+        Some(_) if !matches!(method, Method::POST | Method::PUT | Method::PATCH) => {
+            let msg = format!(
+                "⚠️ [NoNap] Ignoring body for {}: {} requests don't send one",
+                &target.url, method
+            );
+            eprint_styled(Style::Warning, &msg);
+            append_target_log(state.clone(), &target.url, LogLevel::Warn, msg);
+            None
+        }
+        body => body.clone(),
+    };
+    if let (Some(_), Some(content_type)) = (&body, &target.content_type) {
+        match HeaderValue::from_str(content_type) {
+            Ok(value) => {
+                headers.insert(reqwest::header::CONTENT_TYPE, value);
+            }
+            Err(e) => {
+                let msg = format!(
+                    "⚠️ [NoNap] Skipping invalid content_type for {}: {}",
+                    &target.url, e
+                );
+                eprint_styled(Style::Warning, &msg);
+                append_target_log(state.clone(), &target.url, LogLevel::Warn, msg);
+            }
+        }
+    }
+
+    let failure_body_limit = target
+        .capture_body_on_failure
+        .then(|| state.lock().failure_body_bytes);
+
+    let ip_family = parse_ip_family(target.ip_family.as_deref())?;
+
+    let cookie_jar = target.persist_cookies.then(|| {
+        let mut locked = state.lock();
+        locked
+            .cookie_jars
+            .entry(target.url.clone())
+            .or_default()
+            .clone()
+    });
+
+    // Redirect policy, local address and cookie jar are fixed per client, so
+    // targets that change any of them need a client of their own
+    let no_redirects = target.follow_redirects == Some(false);
+    let client = if no_redirects || ip_family.is_some() || cookie_jar.is_some() {
+        let mut builder = client_builder()?;
+        if no_redirects {
+            builder = builder.redirect(redirect::Policy::none());
+        }
+        if let Some(family) = ip_family {
+            builder = builder.local_address(family.unspecified());
+        }
+        if let Some(jar) = cookie_jar {
+            builder = builder.cookie_provider(jar);
+        }
+        Some(
+            builder
+                .build()
+                .map_err(|e| format!("Failed to build HTTP client: {}", e))?,
+        )
+    } else {
+        None
+    };
+
+    Ok(RequestSpec {
+        url,
+        method,
+        headers,
+        failure_body_limit,
+        body_matcher: BodyMatcher::from_target(target)?,
+        client,
+        body,
+        basic_auth: basic_auth_credentials(target)?,
+        hmac: HmacSigner::from_target(target)?,
+        probe,
+        ip_family,
+    })
+}
+
+/// Runs one target's schedule until cancelled. `warm_up` is set for only one
+/// of a target's parallel loops, so its warmup burst is sent once.
+async fn ping_loop(
+    target: PingTarget,
+    client: Client,
+    state: SharedState,
+    cancel: CancellationToken,
+    warm_up: bool,
+) {
+    let resolved = prepare_request(&target, &state).and_then(|spec| {
+        Ok((
+            spec,
+            delay_unit(target.unit.as_deref())?,
+            QuietHours::from_target(&target)?,
+        ))
+    });
+    let (spec, (unit_name, unit_secs), quiet_hours) = match resolved {
+        Ok(r) => r,
+        Err(e) => {
+            let msg = format!("❌ [NoNap] Not pinging {}: {}", &target.url, e);
+            eprint_styled(Style::Failure, &msg);
+            append_target_log(state.clone(), &target.url, LogLevel::Error, msg);
+            return;
+        }
+    };
+
+    if warm_up
+        && !quiet_hours.is_some_and(|q| q.contains(Utc::now()))
+        && !warmup(&target, &spec, &client, &state, &cancel).await
+    {
+        return;
+    }
+
+    let mut prev_mult = 1;
+    let mut last_wake: Option<tokio::time::Instant> = None;
+    // Set by a 429/503 with Retry-After, for the sleep before the next ping
+    let mut retry_after: Option<Duration> = None;
+
+    loop {
+        let (consecutive_failures, remaining, circuit_open) = {
+            let locked = state.lock();
+            if !locked.running {
+                break;
+            }
+            // Exit if the target was disabled or removed since this loop was spawned
+            if !locked
+                .targets
+                .iter()
+                .any(|t| t.url == target.url && t.enabled)
+            {
+                break;
+            }
+            let stats = locked.stats.get(&target.url).cloned().unwrap_or_default();
+            (
+                stats.consecutive_failures,
+                remaining_pings(&target, &stats),
+                locked.circuits.contains_key(&target.url),
+            )
+        };
+
+        if remaining == Some(0) {
+            let msg = format!(
+                "💸 [NoNap] Ping budget exhausted for {} after {} pings, stopping",
+                &target.url,
+                target.max_pings.unwrap_or(0)
+            );
+            print_styled(Style::Dim, &msg);
+            append_target_log(state.clone(), &target.url, LogLevel::Info, msg);
+            break;
+        }
+
+        let mult = backoff_multiplier(
+            target.max_backoff_mult,
+            target.backoff_after.unwrap_or(DEFAULT_BACKOFF_AFTER),
+            consecutive_failures,
+        );
+        if mult > 1 && prev_mult == 1 {
+            let msg = format!(
+                "🐢 [NoNap] Backing off {} after {} consecutive failures",
+                &target.url, consecutive_failures
+            );
+            print_styled(Style::Warning, &msg);
+            append_target_log(state.clone(), &target.url, LogLevel::Warn, msg);
+        } else if mult == 1 && prev_mult > 1 {
+            let msg = format!("🐇 [NoNap] Backoff reset for {}", &target.url);
+            print_styled(Style::Success, &msg);
+            append_target_log(state.clone(), &target.url, LogLevel::Warn, msg);
+        }
+        prev_mult = mult;
+
+        let now = tokio::time::Instant::now();
+        let mut sleep_for = match target.circuit_breaker.as_ref().filter(|_| circuit_open) {
+            Some(breaker) => {
+                let msg = format!(
+                    "🔌 [NoNap] Circuit open for {}, probing again in {}s",
+                    &target.url,
+                    breaker.reset_timeout_secs()
+                );
+                print_styled(Style::Dim, &msg);
+                append_target_log(state.clone(), &target.url, LogLevel::Info, msg);
+                apply_min_interval(&state, Some(&target.url), breaker.reset_timeout_secs())
+            }
+            None => {
+                let delay = base_delay(&target).saturating_mul(mult);
+                let msg = format!(
+                    "🛌 [NoNap] Sleeping {} {} before pinging {}",
+                    delay, unit_name, &target.url
+                );
+                print_styled(Style::Dim, &msg);
+                append_target_log(state.clone(), &target.url, LogLevel::Info, msg);
+                apply_min_interval(&state, Some(&target.url), delay.saturating_mul(unit_secs))
+            }
+        };
+        // A huge interval times a backoff multiplier would overflow the Instant
+        sleep_for = sleep_for.min(MAX_SLEEP);
+        let honored = retry_after.take().filter(|wait| *wait > sleep_for);
+        if let Some(wait) = honored {
+            let msg = format!(
+                "🚦 [NoNap] {} asked to retry after {}s, waiting that long instead to honor the server's backoff",
+                &target.url,
+                wait.as_secs()
+            );
+            print_styled(Style::Warning, &msg);
+            append_target_log(state.clone(), &target.url, LogLevel::Warn, msg);
+            sleep_for = wait;
+        }
+        let wake_at = match (target.interval, last_wake) {
+            // Fixed cadence: measure from the previous wake-up so request time doesn't drift it
+            (Some(_), Some(prev)) if mult == 1 && !circuit_open && honored.is_none() => {
+                (prev + sleep_for).max(now)
+            }
+            _ => now + sleep_for,
+        };
+        last_wake = Some(wake_at);
+        set_next_ping(&state, &target.url, wake_at.saturating_duration_since(now));
+
+        // Wake immediately on cancellation instead of finishing the sleep
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = sleep_until(wake_at) => {}
+        }
+
+        if state.lock().paused {
+            let msg = format!("⏸️ [NoNap] Paused, skipping ping of {}", &target.url);
+            print_styled(Style::Dim, &msg);
+            append_target_log(state.clone(), &target.url, LogLevel::Info, msg);
+            continue;
+        }
+
+        if quiet_hours.is_some_and(|q| q.contains(Utc::now())) {
+            let msg = format!("🌙 [NoNap] Quiet hours for {}, skipping", &target.url);
+            print_styled(Style::Dim, &msg);
+            append_target_log(state.clone(), &target.url, LogLevel::Info, msg);
+            continue;
+        }
+
+        let outcome = tokio::select! {
+            _ = cancel.cancelled() => break,
+            outcome = ping_once(&target, &spec, &client, &state) => outcome,
+        };
+        if let Some(breaker) = &target.circuit_breaker {
+            update_circuit(&state, &target.url, breaker, outcome.success);
+        }
+        retry_after = outcome.retry_after;
+    }
+
+    // A cancelled loop's entry is cleared by whoever cancelled it, possibly
+    // after a replacement loop has already set its own
+    if !cancel.is_cancelled() {
+        state.lock().next_ping.remove(&target.url);
+    }
+}
+
+/// Opens a target's circuit once its failure streak trips the breaker, and
+/// closes it again on the first successful probe.
+fn update_circuit(state: &SharedState, url: &str, breaker: &CircuitBreaker, success: bool) {
+    let (level, msg) = {
+        let mut locked = state.lock();
+        if success {
+            if locked.circuits.remove(url).is_none() {
+                return;
+            }
+            let msg = format!(
+                "🔌 [NoNap] Circuit closed for {}, back on the normal schedule",
+                url
+            );
+            (LogLevel::Warn, msg)
+        } else {
+            let stats = locked.stats.get(url).cloned().unwrap_or_default();
+            if locked.circuits.contains_key(url) || !breaker.trips(&stats) {
+                return;
+            }
+            locked.circuits.insert(url.to_string(), Utc::now());
+            let msg = format!(
+                "🔌 [NoNap] Circuit open for {} after {} consecutive failures, probing every {}s",
+                url,
+                stats.consecutive_failures,
+                breaker.reset_timeout_secs()
+            );
+            (LogLevel::Error, msg)
+        }
+    };
+    if level == LogLevel::Error {
+        eprint_styled(Style::Failure, &msg);
+    } else {
+        print_styled(Style::Success, &msg);
+    }
+    append_target_log(state.clone(), url, level, msg);
+}
+
+/// Records when a target's next ping is due, `after` from now.
+fn set_next_ping(state: &SharedState, url: &str, after: Duration) {
+    let at = Utc::now() + chrono::Duration::from_std(after).unwrap_or(chrono::Duration::zero());
+    state.lock().next_ping.insert(url.to_string(), at);
+}
+
+/// Sends a target's `warmup_pings` `WARMUP_INTERVAL` apart, within its
+/// remaining ping budget. Returns false if the loop was cancelled meanwhile.
+async fn warmup(
+    target: &PingTarget,
+    spec: &RequestSpec,
+    client: &Client,
+    state: &SharedState,
+    cancel: &CancellationToken,
+) -> bool {
+    let count = {
+        let locked = state.lock();
+        if locked.paused {
+            return true;
+        }
+        let stats = locked.stats.get(&target.url).cloned().unwrap_or_default();
+        let count = u64::from(target.warmup_pings.unwrap_or(0));
+        remaining_pings(target, &stats).map_or(count, |left| count.min(left))
+    };
+    if count == 0 {
+        return true;
+    }
+
+    let msg = format!(
+        "🔥 [NoNap] Warming up {} with {} pings {}s apart",
+        &target.url,
+        count,
+        WARMUP_INTERVAL.as_secs()
+    );
+    print_styled(Style::Dim, &msg);
+    append_target_log(state.clone(), &target.url, LogLevel::Info, msg);
+
+    for i in 0..count {
+        if i > 0 {
+            tokio::select! {
+                _ = cancel.cancelled() => return false,
+                _ = sleep(WARMUP_INTERVAL) => {}
+            }
+        }
+        tokio::select! {
+            _ = cancel.cancelled() => return false,
+            _ = ping_once(target, spec, client, state) => {}
+        }
+    }
+
+    let msg = format!(
+        "🔥 [NoNap] Warmup of {} done, resuming the normal schedule",
+        &target.url
+    );
+    print_styled(Style::Dim, &msg);
+    append_target_log(state.clone(), &target.url, LogLevel::Info, msg);
+    true
+}
+
+/// Resolves a delay unit to its name and length in seconds.
+fn delay_unit(unit: Option<&str>) -> Result<(&'static str, u64), String> {
+    match unit.map(|u| u.to_ascii_lowercase()).as_deref() {
+        Some("seconds") => Ok(("seconds", 1)),
+        None | Some("minutes") => Ok(("minutes", 60)),
+        Some("hours") => Ok(("hours", 3600)),
+        Some(other) => Err(format!(
+            "unsupported unit {:?}, expected seconds, minutes or hours",
+            other
+        )),
+    }
+}
+
+/// Clamps a delay to `NONAP_MIN_INTERVAL_SECS` so a misconfigured target
+/// can't be pinged in a tight loop, warning whenever that happens.
+fn apply_min_interval(state: &SharedState, url: Option<&str>, delay_secs: u64) -> Duration {
+    let floor = state.lock().min_interval_secs;
+    if delay_secs >= floor {
+        return Duration::from_secs(delay_secs);
+    }
+
+    let msg = format!(
+        "⚠️ [NoNap] Delay of {}s{} is below the {}s minimum, using {}s",
+        delay_secs,
+        url.map(|u| format!(" for {}", u)).unwrap_or_default(),
+        floor,
+        floor
+    );
+    eprint_styled(Style::Warning, &msg);
+    match url {
+        Some(url) => append_target_log(state.clone(), url, LogLevel::Warn, msg),
+        None => append_log(state.clone(), LogLevel::Warn, msg),
+    }
+    Duration::from_secs(floor)
+}
+
+/// Pings left in the target's `max_pings` budget; every completed check counts.
+fn remaining_pings(target: &PingTarget, stats: &TargetStats) -> Option<u64> {
+    let used = stats.success_count + stats.failure_count;
+    target.max_pings.map(|max| max.saturating_sub(used))
+}
+
+/// Next delay in the target's unit before backoff: the fixed interval when set, otherwise
+/// a random pick from `base_delay ± jitter_pct` or the min/max range.
+fn base_delay(target: &PingTarget) -> u64 {
+    if let Some(interval) = target.interval {
+        return interval;
+    }
+    if let Some(base) = target.base_delay {
+        // Round the spread so small bases still get some jitter
+        let spread = base
+            .saturating_mul(u64::from(target.jitter_pct.unwrap_or(0)))
+            .saturating_add(50)
+            / 100;
+        return rand::thread_rng()
+            .gen_range(base.saturating_sub(spread)..=base.saturating_add(spread));
+    }
+    let min = target.min_delay.unwrap_or(0);
+    let max = target.max_delay.unwrap_or(min).max(min);
+    rand::thread_rng().gen_range(min..=max)
+}
+
+/// Doubles the delay for every failure from the `after`th on, up to `max_mult`.
+/// Without `max_mult` backoff is disabled and the multiplier is always 1.
+fn backoff_multiplier(max_mult: Option<u64>, after: u64, consecutive_failures: u64) -> u64 {
+    let Some(max_mult) = max_mult else {
+        return 1;
+    };
+    let after = after.max(1);
+    if consecutive_failures < after {
+        return 1;
+    }
+    let exponent = (consecutive_failures - after + 1).min(32) as u32;
+    2u64.saturating_pow(exponent).min(max_mult.max(1))
+}
+
+/// Spawns every enabled target, spreading first pings over `NONAP_STAGGER_SECS`
+/// so a large config doesn't fire all at once.
+fn spawn_all_workers(state: &SharedState, locked: &mut AppState) {
+    let enabled: Vec<PingTarget> = locked
+        .targets
+        .iter()
+        .filter(|t| t.enabled)
+        .cloned()
+        .collect();
+    spawn_staggered(state, locked, enabled);
+}
+
+fn spawn_staggered(state: &SharedState, locked: &mut AppState, enabled: Vec<PingTarget>) {
+    let window_ms = locked.stagger_secs.saturating_mul(1000);
+    let count = enabled.len() as u64;
+    for (i, target) in enabled.into_iter().enumerate() {
+        let offset =
+            Duration::from_millis(window_ms.saturating_mul(i as u64) / count.max(1)).min(MAX_SLEEP);
+        spawn_worker_after(state, locked, target, offset);
+    }
+}
+
+/// Spawns the ping loop for one target, replacing (and cancelling) any loop
+/// already registered for its URL. Grouped targets instead make sure their
+/// group's loop is running. Must be called with the state lock held.
+fn spawn_worker(state: &SharedState, locked: &mut AppState, target: PingTarget) {
+    spawn_worker_after(state, locked, target, Duration::ZERO);
+}
+
+/// Like `spawn_worker`, but the loop waits `offset` before its first iteration.
+fn spawn_worker_after(
+    state: &SharedState,
+    locked: &mut AppState,
+    target: PingTarget,
+    offset: Duration,
+) {
+    // Every loop uses the shared client, keeping one connection pool and config
+    let client = locked.client.clone();
+    if let Some(group) = &target.group {
+        let key = group_worker_key(group);
+        if locked
+            .handles
+            .get(&key)
+            .is_some_and(|(handle, token)| !handle.is_finished() && !token.is_cancelled())
+        {
+            return;
+        }
+        let token = locked.cancel.child_token();
+        let (g, c, s, t) = (group.clone(), client, state.clone(), token.clone());
+        let handle = tokio::spawn(async move {
+            if stagger(&s, None, &format!("group {}", g), offset, &t).await {
+                group_loop(g, c, s, t).await;
+            }
+        });
+        if let Some((_, old)) = locked.handles.insert(key, (handle, token)) {
+            old.cancel();
+        }
+        return;
+    }
+
+    let url = target.url.clone();
+    // The new loop records its own schedule; until then there is none
+    locked.next_ping.remove(&url);
+    let token = locked.cancel.child_token();
+    let (c, s, t) = (client, state.clone(), token.clone());
+    // Parallel loops share one task and token, so stop/remove cancel them together
+    let handle = tokio::spawn(async move {
+        if !stagger(&s, Some(&target.url), &target.url, offset, &t).await {
+            return;
+        }
+        let loops = (0..target.concurrency.unwrap_or(1))
+            .map(|i| ping_loop(target.clone(), c.clone(), s.clone(), t.clone(), i == 0));
+        join_all(loops).await;
+    });
+    if let Some((_, old)) = locked.handles.insert(url, (handle, token)) {
+        old.cancel();
+    }
+}
+
+/// Waits out a worker's startup offset, logging it. Returns false if the worker
+/// was cancelled meanwhile.
+async fn stagger(
+    state: &SharedState,
+    url: Option<&str>,
+    what: &str,
+    offset: Duration,
+    cancel: &CancellationToken,
+) -> bool {
+    if offset.is_zero() {
+        return true;
+    }
+    let msg = format!(
+        "⏳ [NoNap] Staggering first ping of {} by {:.1}s",
+        what,
+        offset.as_secs_f64()
+    );
+    print_styled(Style::Dim, &msg);
+    push_log(state.clone(), url.map(str::to_string), LogLevel::Info, msg);
+    if let Some(url) = url {
+        set_next_ping(state, url, offset);
+    }
+    tokio::select! {
+        _ = cancel.cancelled() => false,
+        _ = sleep(offset) => true,
+    }
+}
+
+/// Group loops share the handle map with per-target loops; the prefix can't
+/// collide with a valid http(s) URL.
+fn group_worker_key(group: &str) -> String {
+    format!("group:{}", group)
+}
+
+/// Pings every enabled member of a group concurrently on one heartbeat.
+/// Membership is re-read each round, so adding, removing or toggling members
+/// takes effect without restarting the loop.
+async fn group_loop(group: String, client: Client, state: SharedState, cancel: CancellationToken) {
+    let mut specs: HashMap<String, (PingTarget, RequestSpec)> = HashMap::new();
+    // The longest `Retry-After` any member sent last round, and who sent it
+    let mut retry_after: Option<(String, Duration)> = None;
+
+    loop {
+        let members: Vec<PingTarget> = {
+            let locked = state.lock();
+            if !locked.running {
+                break;
+            }
+            locked
+                .targets
+                .iter()
+                .filter(|t| t.enabled && t.group.as_deref() == Some(group.as_str()))
+                .filter(|t| {
+                    let stats = locked.stats.get(&t.url).cloned().unwrap_or_default();
+                    remaining_pings(t, &stats) != Some(0)
+                })
+                .cloned()
+                .collect()
+        };
+        let Some(leader) = members.first() else {
+            break;
+        };
+
+        let (unit_name, unit_secs) = delay_unit(leader.unit.as_deref()).unwrap_or(("minutes", 60));
+        let delay = base_delay(leader);
+        let msg = format!(
+            "🛌 [NoNap] Sleeping {} {} before pinging group {} ({} targets)",
+            delay,
+            unit_name,
+            group,
+            members.len()
+        );
+        print_styled(Style::Dim, &msg);
+        append_log(state.clone(), LogLevel::Info, msg);
+
+        // A huge interval would overflow the Instant, as in `ping_loop`
+        let mut sleep_for =
+            apply_min_interval(&state, None, delay.saturating_mul(unit_secs)).min(MAX_SLEEP);
+        // Members fire together, so the slowest backoff request holds the whole group
+        if let Some((url, wait)) = retry_after.take().filter(|(_, wait)| *wait > sleep_for) {
+            let msg = format!(
+                "🚦 [NoNap] {} asked to retry after {}s, waiting that long before pinging group {} to honor the server's backoff",
+                url,
+                wait.as_secs(),
+                group
+            );
+            print_styled(Style::Warning, &msg);
+            append_log(state.clone(), LogLevel::Warn, msg);
+            sleep_for = wait;
+        }
+        for member in &members {
+            set_next_ping(&state, &member.url, sleep_for);
+        }
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = sleep(sleep_for) => {}
+        }
+
+        if state.lock().paused {
+            let msg = format!("⏸️ [NoNap] Paused, skipping ping of group {}", group);
+            print_styled(Style::Dim, &msg);
+            append_log(state.clone(), LogLevel::Info, msg);
+            continue;
+        }
+
+        // Rebuild a member's spec only when its config changed
+        specs.retain(|url, _| members.iter().any(|m| &m.url == url));
+        for member in &members {
+            if specs.get(&member.url).is_some_and(|(t, _)| t == member) {
+                continue;
+            }
+            match prepare_request(member, &state) {
+                Ok(spec) => {
+                    specs.insert(member.url.clone(), (member.clone(), spec));
+                }
+                Err(e) => {
+                    specs.remove(&member.url);
+                    let msg = format!("❌ [NoNap] Not pinging {}: {}", &member.url, e);
+                    eprint_styled(Style::Failure, &msg);
+                    append_target_log(state.clone(), &member.url, LogLevel::Error, msg);
+                }
+            }
+        }
+
+        let now = Utc::now();
+        let pings = specs
+            .values()
+            .filter(|(target, _)| {
+                !QuietHours::from_target(target)
+                    .ok()
+                    .flatten()
+                    .is_some_and(|q| q.contains(now))
+            })
+            .map(|(target, spec)| ping_once(target, spec, &client, &state));
+
+        let outcomes = tokio::select! {
+            _ = cancel.cancelled() => break,
+            outcomes = join_all(pings) => outcomes,
+        };
+        retry_after = outcomes
+            .into_iter()
+            .filter_map(|outcome| outcome.retry_after.map(|wait| (outcome.url, wait)))
+            .max_by_key(|(_, wait)| *wait);
+    }
+}
+
+/// Cancels the ping loop for one target, returning its handle if it had one.
+fn cancel_worker(locked: &mut AppState, url: &str) -> Option<JoinHandle<()>> {
+    locked.next_ping.remove(url);
+    locked.handles.remove(url).map(|(handle, token)| {
+        token.cancel();
+        handle
+    })
+}
+
+/// Drops everything kept per target URL for a target that is gone.
+fn forget_target(locked: &mut AppState, url: &str) {
+    locked.stats.remove(url);
+    locked.recent.remove(url);
+    locked.validators.remove(url);
+    locked.cookie_jars.remove(url);
+    locked.circuits.remove(url);
+}
+
+/// Waits for cancelled workers to exit, aborting any that overrun the shared
+/// deadline. Returns how many exited on their own.
+async fn await_workers(workers: HashMap<String, Worker>) -> usize {
+    let deadline = tokio::time::Instant::now() + WORKER_STOP_TIMEOUT;
+    let mut stopped = 0;
+    for (_, (mut handle, _)) in workers {
+        match tokio::time::timeout_at(deadline, &mut handle).await {
+            Ok(_) => stopped += 1,
+            Err(_) => handle.abort(),
+        }
+    }
+    stopped
+}
+
+#[derive(Debug, Serialize)]
+struct PingOutcome {
+    url: String,
+    success: bool,
+    status: Option<u16>,
+    latency_ms: u64,
+    error: Option<String>,
+    /// What kind of failure this was; unset on success
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<ErrorCategory>,
+    /// How long a 429 or 503 response asked us to wait via `Retry-After`
+    #[serde(skip)]
+    retry_after: Option<Duration>,
+    /// Human-readable log line describing the attempt
+    #[serde(skip)]
+    message: String,
+}
+
+/// Why a check failed, counted per target so DNS trouble can be told apart
+/// from the service itself being down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ErrorCategory {
+    Dns,
+    Connect,
+    Timeout,
+    Tls,
+    /// A response arrived with a status outside the expected ones
+    Status,
+    /// The response failed `body_contains`/`body_regex` or `max_response_bytes`
+    Body,
+    /// Any other failure while sending the request or reading the response
+    Request,
+    Other,
+}
+
+impl ErrorCategory {
+    fn name(self) -> &'static str {
+        match self {
+            ErrorCategory::Dns => "dns",
+            ErrorCategory::Connect => "connect",
+            ErrorCategory::Timeout => "timeout",
+            ErrorCategory::Tls => "tls",
+            ErrorCategory::Status => "status",
+            ErrorCategory::Body => "body",
+            ErrorCategory::Request => "request",
+            ErrorCategory::Other => "other",
+        }
+    }
+
+    /// reqwest only flags timeouts and connect errors; DNS and TLS failures
+    /// are told apart by the messages of the underlying hyper and TLS errors.
+    fn of_request_error(e: &reqwest::Error) -> Self {
+        if e.is_timeout() {
+            return ErrorCategory::Timeout;
+        }
+        let mut chain = String::new();
+        let mut source: Option<&dyn std::error::Error> = Some(e);
+        while let Some(err) = source {
+            chain.push_str(&err.to_string().to_lowercase());
+            chain.push('\n');
+            source = err.source();
+        }
+        if chain.contains("dns error") || chain.contains("failed to lookup address") {
+            ErrorCategory::Dns
+        } else if ["tls", "ssl", "certificate", "handshake"]
+            .iter()
+            .any(|needle| chain.contains(needle))
+        {
+            ErrorCategory::Tls
+        } else if e.is_connect() {
+            ErrorCategory::Connect
+        } else if e.is_request() || e.is_body() {
+            ErrorCategory::Request
+        } else {
+            ErrorCategory::Other
+        }
+    }
+}
+
+/// Performs a check against a target, retrying failed attempts up to the
+/// target's `retries`, then records stats and logs the final result.
+async fn ping_once(
+    target: &PingTarget,
+    spec: &RequestSpec,
+    client: &Client,
+    state: &SharedState,
+) -> PingOutcome {
+    let retries = target.retries.unwrap_or(0);
+    let mut attempt = 0;
+
+    let outcome = loop {
+        // Held for the attempt only, so a retry's delay doesn't block other targets
+        let permit = acquire_request_slot(state, &target.url).await;
+        let outcome = match &spec.probe {
+            Some(Probe::Tcp(address)) => connect_tcp(target, address, spec.ip_family).await,
+            Some(Probe::Dns(host)) => resolve_dns(target, host, spec.ip_family).await,
+            None => {
+                let cached = if target.conditional {
+                    let locked = state.lock();
+                    locked
+                        .validators
+                        .get(&target.url)
+                        .cloned()
+                        .unwrap_or_default()
+                } else {
+                    CacheValidators::default()
+                };
+                let (outcome, fresh) = send_request(target, spec, client, &cached).await;
+                if let Some(fresh) = fresh {
+                    state.lock().validators.insert(target.url.clone(), fresh);
+                }
+                outcome
+            }
+        };
+        drop(permit);
+        // The loop honors a Retry-After; retrying sooner would ignore it
+        if outcome.success || attempt >= retries || outcome.retry_after.is_some() {
+            break outcome;
+        }
+        attempt += 1;
+        let msg = format!(
+            "🔁 [NoNap] Retrying {} ({}/{}) in {}s after failure: {}",
+            &target.url,
+            attempt,
+            retries,
+            RETRY_DELAY.as_secs(),
+            outcome.error.as_deref().unwrap_or("unknown error")
+        );
+        eprint_styled(Style::Warning, &msg);
+        append_target_log(state.clone(), &target.url, LogLevel::Warn, msg);
+        sleep(RETRY_DELAY).await;
+    };
+
+    if outcome.success {
+        print_styled(Style::Success, &outcome.message);
+    } else {
+        eprint_styled(Style::Failure, &outcome.message);
+    }
+    let level = if outcome.success {
+        LogLevel::Info
+    } else {
+        LogLevel::Error
+    };
+    append_target_log(state.clone(), &target.url, level, outcome.message.clone());
+    let (prev, stats) = record_result(state, &outcome);
+    record_history(state, &outcome);
+
+    let prev_state = health_state(target, prev.consecutive_failures);
+    let new_state = health_state(target, stats.consecutive_failures);
+    if new_state == prev_state {
+        return outcome;
+    }
+
+    if new_state != HealthState::Healthy {
+        let msg = format!(
+            "{} [NoNap] {} is {} after {} consecutive failures (was {})",
+            if new_state == HealthState::Down {
+                "🔴"
+            } else {
+                "🟠"
+            },
+            &target.url,
+            new_state.name(),
+            stats.consecutive_failures,
+            prev_state.name()
+        );
+        let (level, style) = if new_state == HealthState::Down {
+            (LogLevel::Error, Style::Failure)
+        } else {
+            (LogLevel::Warn, Style::Warning)
+        };
+        eprint_styled(style, &msg);
+        append_target_log(state.clone(), &target.url, level, msg);
+
+        let payload = serde_json::json!({
+            "event": new_state.name(),
+            "url": outcome.url,
+            "state": new_state,
+            "previous_state": prev_state,
+            "status": outcome.status,
+            "error": outcome.error,
+            "category": outcome.category,
+            "timestamp": stats.last_checked,
+            "consecutive_failures": stats.consecutive_failures,
+            "failing_secs": secs_since(stats.failing_since.as_deref()),
+        });
+        notify_webhook(state, client, payload);
+    } else {
+        // Based on stored stats rather than loop state, so backoff doesn't hide it
+        let downtime_secs = secs_since(prev.failing_since.as_deref());
+        let msg = format!(
+            "💚 [NoNap] {} recovered after {} failed checks{}",
+            &target.url,
+            prev.consecutive_failures,
+            downtime_secs.map_or(String::new(), |d| format!(" ({}s down)", d))
+        );
+        print_styled(Style::Success, &msg);
+        append_target_log(state.clone(), &target.url, LogLevel::Warn, msg);
+
+        let payload = serde_json::json!({
+            "event": "recovered",
+            "url": outcome.url,
+            "state": new_state,
+            "previous_state": prev_state,
+            "status": outcome.status,
+            "timestamp": stats.last_checked,
+            "failed_checks": prev.consecutive_failures,
+            "downtime_secs": downtime_secs,
+        });
+        notify_webhook(state, client, payload);
+    }
+
+    outcome
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum HealthState {
+    Healthy,
+    /// Failing, but fewer than `down_after` checks in a row
+    Degraded,
+    Down,
+}
+
+impl HealthState {
+    fn name(self) -> &'static str {
+        match self {
+            HealthState::Healthy => "healthy",
+            HealthState::Degraded => "degraded",
+            HealthState::Down => "down",
+        }
+    }
+}
+
+/// Derived from the failure streak, so it survives restarts along with the stats.
+fn health_state(target: &PingTarget, consecutive_failures: u64) -> HealthState {
+    match consecutive_failures {
+        0 => HealthState::Healthy,
+        n if n >= target.down_after.unwrap_or(DEFAULT_DOWN_AFTER) => HealthState::Down,
+        _ => HealthState::Degraded,
+    }
+}
+
+/// Seconds elapsed since an RFC3339 timestamp.
+fn secs_since(timestamp: Option<&str>) -> Option<i64> {
+    let since = chrono::DateTime::parse_from_rfc3339(timestamp?).ok()?;
+    Some((chrono::Utc::now() - since.with_timezone(&chrono::Utc)).num_seconds())
+}
+
+/// Shape of the webhook body, from `NONAP_ALERT_TARGET`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AlertFormat {
+    /// The raw event JSON
+    Generic,
+    Slack,
+    Discord,
+}
+
+impl AlertFormat {
+    fn from_env() -> Self {
+        match std::env::var("NONAP_ALERT_TARGET")
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "" | "generic" => AlertFormat::Generic,
+            "slack" => AlertFormat::Slack,
+            "discord" => AlertFormat::Discord,
+            other => {
+                eprintln!(
+                    "⚠️ [NoNap] Invalid NONAP_ALERT_TARGET {:?}, expected slack, discord or generic; using generic",
+                    other
+                );
+                AlertFormat::Generic
+            }
+        }
+    }
+
+    /// Renders an event payload from `ping_once` for the chat platform.
+    fn render(self, event: serde_json::Value) -> serde_json::Value {
+        if self == AlertFormat::Generic {
+            return event;
+        }
+        let url = event["url"].as_str().unwrap_or_default();
+        let recovered = event["event"] == "recovered";
+        let status = match event["status"].as_u64() {
+            Some(status) => status.to_string(),
+            None => "no response".to_string(),
+        };
+        let duration = if recovered {
+            event["downtime_secs"].as_i64()
+        } else {
+            event["failing_secs"].as_i64()
+        };
+        let duration = duration.map_or("unknown".to_string(), |d| format!("{}s", d));
+        let (text, color) = if recovered {
+            (
+                format!("💚 {} recovered after {} down", url, duration),
+                0x5cb85c,
+            )
+        } else {
+            let state = event["event"].as_str().unwrap_or_default();
+            let icon = if state == "down" { "🔴" } else { "🟠" };
+            (format!("{} {} is {}", icon, url, state), 0xd9534f)
+        };
+        let mut fields = vec![("URL", url.to_string()), ("Status", status)];
+        fields.push((if recovered { "Down for" } else { "Failing for" }, duration));
+        if let Some(error) = event["error"].as_str() {
+            fields.push(("Error", error.to_string()));
+        }
+
+        match self {
+            AlertFormat::Slack => serde_json::json!({
+                "text": text,
+                "attachments": [{
+                    "color": format!("#{:06x}", color),
+                    "fields": fields
+                        .iter()
+                        .map(|(title, value)| serde_json::json!({
+                            "title": title,
+                            "value": value,
+                            "short": *title != "Error",
+                        }))
+                        .collect::<Vec<_>>(),
+                }],
+            }),
+            _ => serde_json::json!({
+                "content": text,
+                "embeds": [{
+                    "title": url,
+                    "color": color,
+                    "fields": fields
+                        .iter()
+                        .map(|(name, value)| serde_json::json!({
+                            "name": name,
+                            "value": value,
+                            "inline": *name != "Error",
+                        }))
+                        .collect::<Vec<_>>(),
+                }],
+            }),
+        }
+    }
+}
+
+/// Fire-and-forget POST to `NONAP_WEBHOOK_URL`, if configured. Delivery
+/// failures are logged but never block or fail the ping loop.
+fn notify_webhook(state: &SharedState, client: &Client, payload: serde_json::Value) {
+    let (webhook_url, format) = {
+        let locked = state.lock();
+        let Some(webhook_url) = locked.webhook_url.clone() else {
+            return;
+        };
+        (webhook_url, locked.alert_format)
+    };
+    let payload = format.render(payload);
+    let client = client.clone();
+    let state = state.clone();
+
+    tokio::spawn(async move {
+        let result = client
+            .post(&webhook_url)
+            .timeout(WEBHOOK_TIMEOUT)
+            .json(&payload)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status());
+        if let Err(e) = result {
+            // Slack/Discord webhook URLs embed their secret token
+            let msg = format!("❌ [NoNap] Webhook delivery failed: {}", e.without_url());
+            eprint_styled(Style::Failure, &msg);
+            append_log(state, LogLevel::Error, msg);
+        }
+    });
+}
+
+/// Validators from a previous response, sent back so the server can answer 304.
+#[derive(Debug, Clone, Default)]
+struct CacheValidators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl CacheValidators {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let get = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        CacheValidators {
+            etag: get(ETAG),
+            last_modified: get(LAST_MODIFIED),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// Waits for a free slot when `NONAP_MAX_CONCURRENCY` is set, logging the wait.
+async fn acquire_request_slot(state: &SharedState, url: &str) -> Option<OwnedSemaphorePermit> {
+    let slots = state.lock().request_slots.clone()?;
+    if let Ok(permit) = slots.clone().try_acquire_owned() {
+        return Some(permit);
+    }
+    let msg = format!(
+        "⏳ [NoNap] Waiting for a free request slot to ping {} (NONAP_MAX_CONCURRENCY reached)",
+        url
+    );
+    print_styled(Style::Dim, &msg);
+    append_target_log(state.clone(), url, LogLevel::Info, msg);
+    // The semaphore is never closed
+    slots.acquire_owned().await.ok()
+}
+
+/// Sends a single request and classifies the response, without side effects.
+/// For `conditional` targets, `cached` is sent along and the validators of a
+/// successful full response are returned for the next check.
+async fn send_request(
+    target: &PingTarget,
+    spec: &RequestSpec,
+    client: &Client,
+    cached: &CacheValidators,
+) -> (PingOutcome, Option<CacheValidators>) {
+    let timeout_secs = target.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS);
+    let started = Instant::now();
+
+    let mut request = spec
+        .client
+        .as_ref()
+        .unwrap_or(client)
+        .request(spec.method.clone(), &spec.url)
+        .headers(spec.headers.clone())
+        .timeout(Duration::from_secs(timeout_secs));
+    if let Some(body) = &spec.body {
+        request = request.body(body.clone());
+    }
+    if let Some((user, password)) = &spec.basic_auth {
+        request = request.basic_auth(user, Some(password));
+    }
+    if let Some(signer) = &spec.hmac {
+        let (timestamp, signature) = signer.sign(Utc::now().timestamp());
+        request = request
+            .header(signer.timestamp_header.clone(), timestamp)
+            .header(signer.header.clone(), signature);
+    }
+    if let Some(etag) = &cached.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &cached.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+    let result = request.send().await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    let mut fresh = None;
+    let outcome = match result {
+        Ok(mut resp) => {
+            let status = resp.status().as_u16();
+            let via = family_note(spec.ip_family, resp.remote_addr());
+            if status == 304 && !cached.is_empty() {
+                let outcome = PingOutcome {
+                    url: target.url.clone(),
+                    success: true,
+                    status: Some(status),
+                    latency_ms,
+                    error: None,
+                    category: None,
+                    retry_after: None,
+                    message: format!(
+                        "✅ [NoNap] {} responded 304 (not modified){} in {}ms",
+                        &target.url, via, latency_ms
+                    ),
+                };
+                return (outcome, None);
+            }
+            if target.conditional && status_is_expected(target, status) {
+                fresh = Some(CacheValidators::from_headers(resp.headers()));
+            }
+            let expected = status_is_expected(target, status);
+            // The body is only read when something needs it, and never past the cap
+            let read_limit = target
+                .max_response_bytes
+                .or(spec.body_matcher.as_ref().map(|_| BODY_MATCH_MAX_BYTES));
+            let mut oversize = None;
+            let mut body = None;
+            if let (Some(limit), true) = (read_limit, expected) {
+                let declared = resp.content_length().filter(|&len| {
+                    target
+                        .max_response_bytes
+                        .is_some_and(|max| len > max as u64)
+                });
+                if let Some(len) = declared {
+                    oversize = Some(format!(
+                        "response of {} bytes exceeds max_response_bytes ({})",
+                        len, limit
+                    ));
+                } else {
+                    let (read, truncated) = read_body_limited(&mut resp, limit).await;
+                    if truncated && target.max_response_bytes.is_some() {
+                        oversize = Some(format!("response exceeds max_response_bytes ({})", limit));
+                    }
+                    body = Some(read);
+                }
+            }
+            let body_mismatch = match (&spec.body_matcher, &body) {
+                (Some(matcher), Some(body)) if oversize.is_none() => (!matcher
+                    .matches(&String::from_utf8_lossy(body)))
+                .then(|| format!("expected body {}", matcher.describe())),
+                _ => None,
+            };
+            // An oversized response only warns unless fail_on_oversize is set
+            let failure =
+                body_mismatch.or_else(|| oversize.clone().filter(|_| target.fail_on_oversize));
+            if let Some(error) = failure {
+                PingOutcome {
+                    url: target.url.clone(),
+                    success: false,
+                    status: Some(status),
+                    latency_ms,
+                    category: Some(ErrorCategory::Body),
+                    retry_after: None,
+                    message: format!(
+                        "⚠️ [NoNap] {} responded {}{} in {}ms: {}",
+                        &target.url, status, via, latency_ms, error
+                    ),
+                    error: Some(error),
+                }
+            } else if expected {
+                let message = match &oversize {
+                    Some(warning) => format!(
+                        "⚠️ [NoNap] {} responded {}{} in {}ms, but {}",
+                        &target.url, status, via, latency_ms, warning
+                    ),
+                    None => format!(
+                        "✅ [NoNap] {} responded {}{} in {}ms",
+                        &target.url, status, via, latency_ms
+                    ),
+                };
+                PingOutcome {
+                    url: target.url.clone(),
+                    success: true,
+                    status: Some(status),
+                    latency_ms,
+                    error: None,
+                    category: None,
+                    retry_after: None,
+                    message,
+                }
+            } else {
+                let mut error = format!(
+                    "unexpected status {} (expected {})",
+                    status,
+                    describe_expected(target)
+                );
+                let retry_after = match status {
+                    429 | 503 => resp
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(parse_retry_after),
+                    _ => None,
+                };
+                let limit = match (spec.failure_body_limit, target.max_response_bytes) {
+                    (Some(limit), Some(max)) => Some(limit.min(max)),
+                    (limit, _) => limit,
+                };
+                if let Some(limit) = limit {
+                    let snippet = read_body_snippet(&mut resp, limit).await;
+                    if !snippet.is_empty() {
+                        error.push_str(&format!("; body: {}", snippet));
+                    }
+                }
+                PingOutcome {
+                    url: target.url.clone(),
+                    success: false,
+                    status: Some(status),
+                    latency_ms,
+                    category: Some(ErrorCategory::Status),
+                    retry_after,
+                    message: format!(
+                        "⚠️ [NoNap] {} responded {}{} in {}ms: {}",
+                        &target.url, status, via, latency_ms, error
+                    ),
+                    error: Some(error),
+                }
+            }
+        }
+        Err(e) => {
+            let category = ErrorCategory::of_request_error(&e);
+            // The request URL is the expanded one, which may carry a ${VAR} secret
+            let e = e.without_url();
+            let message = if category == ErrorCategory::Timeout {
+                format!(
+                    "⏱️ [NoNap] Timed out pinging {} after {}s",
+                    &target.url, timeout_secs
+                )
+            } else {
+                format!(
+                    "❌ [NoNap] Failed to ping {} ({}): {}",
+                    &target.url,
+                    category.name(),
+                    e
+                )
+            };
+            PingOutcome {
+                url: target.url.clone(),
+                success: false,
+                status: None,
+                latency_ms,
+                error: Some(e.to_string()),
+                category: Some(category),
+                retry_after: None,
+                message,
+            }
+        }
+    };
+    // A body mismatch mustn't be locked in by later 304s
+    let fresh = fresh.filter(|_| outcome.success);
+    (outcome, fresh)
+}
+
+/// Reads a `Retry-After` value, either delay seconds or an HTTP-date. Dates
+/// in the past mean no wait; anything longer than a day is capped.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let wait = match value.parse::<u64>() {
+        Ok(secs) => Duration::from_secs(secs),
+        Err(_) => {
+            let at = DateTime::parse_from_rfc2822(value).ok()?;
+            (at.with_timezone(&Utc) - Utc::now()).to_std().ok()?
+        }
+    };
+    Some(wait.min(MAX_RETRY_AFTER))
+}
+
+/// Opens (and immediately drops) a TCP connection, without side effects.
+async fn connect_tcp(target: &PingTarget, address: &str, family: Option<IpFamily>) -> PingOutcome {
+    let timeout_secs = target.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS);
+    let started = Instant::now();
+
+    let connect = async {
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host(address)
+            .await
+            .map_err(|e| (ErrorCategory::Dns, e))?
+            .filter(|a| family.is_none_or(|f| f.matches(a.ip())))
+            .collect();
+        if addrs.is_empty() {
+            let error = match family {
+                Some(f) => format!("no {} addresses for {}", f.label(), address),
+                None => format!("no addresses for {}", address),
+            };
+            return Err((ErrorCategory::Dns, std::io::Error::other(error)));
+        }
+        tokio::net::TcpStream::connect(&addrs[..])
+            .await
+            .map_err(|e| (ErrorCategory::Connect, e))
+    };
+    let result = tokio::time::timeout(Duration::from_secs(timeout_secs), connect).await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    let (category, error, message) = match result {
+        Ok(Ok(stream)) => {
+            return PingOutcome {
+                url: target.url.clone(),
+                success: true,
+                status: None,
+                latency_ms,
+                error: None,
+                category: None,
+                retry_after: None,
+                message: format!(
+                    "✅ [NoNap] {} accepted a TCP connection{} in {}ms",
+                    &target.url,
+                    family_note(family, stream.peer_addr().ok()),
+                    latency_ms
+                ),
+            };
+        }
+        Ok(Err((category, e))) => (
+            category,
+            e.to_string(),
+            format!("❌ [NoNap] Failed to connect to {}: {}", &target.url, e),
+        ),
+        Err(_) => (
+            ErrorCategory::Timeout,
+            format!("connect timed out after {}s", timeout_secs),
+            format!(
+                "⏱️ [NoNap] Timed out connecting to {} after {}s",
+                &target.url, timeout_secs
+            ),
+        ),
+    };
+    PingOutcome {
+        url: target.url.clone(),
+        success: false,
+        status: None,
+        latency_ms,
+        error: Some(error),
+        category: Some(category),
+        retry_after: None,
+        message,
+    }
+}
+
+/// Resolves the target's host, without side effects. No records counts as a failure.
+async fn resolve_dns(target: &PingTarget, host: &str, family: Option<IpFamily>) -> PingOutcome {
+    let timeout_secs = target.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS);
+    let started = Instant::now();
+
+    // lookup_host wants a port; it doesn't affect resolution
+    let result = tokio::time::timeout(
+        Duration::from_secs(timeout_secs),
+        tokio::net::lookup_host(format!("{}:0", host)),
+    )
+    .await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    let (category, error, message) = match result {
+        Ok(Ok(addrs)) => {
+            let addrs: Vec<String> = addrs
+                .map(|a| a.ip())
+                .filter(|ip| family.is_none_or(|f| f.matches(*ip)))
+                .map(|ip| ip.to_string())
+                .collect();
+            if addrs.is_empty() {
+                let error = match family {
+                    Some(f) => format!("no {} addresses returned", f.label()),
+                    None => "no addresses returned".to_string(),
+                };
+                (
+                    ErrorCategory::Dns,
+                    error.clone(),
+                    format!("❌ [NoNap] Failed to resolve {}: {}", &target.url, error),
+                )
+            } else {
+                return PingOutcome {
+                    url: target.url.clone(),
+                    success: true,
+                    status: None,
+                    latency_ms,
+                    error: None,
+                    category: None,
+                    retry_after: None,
+                    message: format!(
+                        "🔎 [NoNap] {} resolved to {} in {}ms",
+                        &target.url,
+                        addrs.join(", "),
+                        latency_ms
+                    ),
+                };
+            }
+        }
+        Ok(Err(e)) => (
+            ErrorCategory::Dns,
+            e.to_string(),
+            format!("❌ [NoNap] Failed to resolve {}: {}", &target.url, e),
+        ),
+        Err(_) => (
+            ErrorCategory::Timeout,
+            format!("resolution timed out after {}s", timeout_secs),
+            format!(
+                "⏱️ [NoNap] Timed out resolving {} after {}s",
+                &target.url, timeout_secs
+            ),
+        ),
+    };
+    PingOutcome {
+        url: target.url.clone(),
+        success: false,
+        status: None,
+        latency_ms,
+        error: Some(error),
+        category: Some(category),
+        retry_after: None,
+        message,
+    }
+}
+
+/// Reads at most `limit` bytes of the body, so a huge response can't fill
+/// memory. The flag is set when the body was cut short.
+async fn read_body_limited(resp: &mut reqwest::Response, limit: usize) -> (Vec<u8>, bool) {
+    let mut body = Vec::new();
+    while let Ok(Some(chunk)) = resp.chunk().await {
+        let room = limit - body.len();
+        if chunk.len() > room {
+            body.extend_from_slice(&chunk[..room]);
+            return (body, true);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    (body, false)
+}
+
+/// Reads the start of the body flattened onto one line for the log.
+async fn read_body_snippet(resp: &mut reqwest::Response, limit: usize) -> String {
+    let (body, truncated) = read_body_limited(resp, limit).await;
+    let mut snippet = String::from_utf8_lossy(&body)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if truncated {
+        snippet.push('…');
+    }
+    snippet
+}
+
+/// Updates the target's stats with an outcome, returning the stats from
+/// before and after the update.
+fn record_result(state: &SharedState, outcome: &PingOutcome) -> (TargetStats, TargetStats) {
+    let now = chrono::Utc::now().to_rfc3339();
+    let mut locked = state.lock();
+    let stats = locked.stats.entry(outcome.url.clone()).or_default();
+    let prev = stats.clone();
+
+    if outcome.success {
+        stats.success_count += 1;
+        stats.clear_failure_streak();
+    } else {
+        stats.failure_count += 1;
+        stats.consecutive_failures += 1;
+        let category = outcome.category.unwrap_or(ErrorCategory::Other);
+        *stats.failures_by_category.entry(category).or_default() += 1;
+        if !stats.was_failing {
+            stats.was_failing = true;
+            stats.failing_since = Some(now.clone());
+        }
+    }
+    // A successful TCP or DNS probe has no status but did get an answer
+    let latency_ms = (outcome.success || outcome.status.is_some()).then_some(outcome.latency_ms);
+    stats.last_status = outcome.status;
+    stats.last_latency_ms = latency_ms;
+    stats.last_checked = Some(now.clone());
+    let stats = stats.clone();
+
+    let window = locked.recent_window;
+    let recent = locked.recent.entry(outcome.url.clone()).or_default();
+    recent.push_back(RecentResult {
+        timestamp: now,
+        success: outcome.success,
+        latency_ms,
+    });
+    while recent.len() > window {
+        recent.pop_front();
+    }
+    (prev, stats)
+}
+
+/// A recent check, kept in memory per target for latency percentiles.
+#[derive(Debug, Clone, Serialize)]
+struct RecentResult {
+    timestamp: String,
+    success: bool,
+    /// Unset when no response (or TCP connection, or DNS answer) arrived
+    latency_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct LatencyPercentiles {
+    samples: usize,
+    p50: u64,
+    p95: u64,
+    p99: u64,
+}
+
+/// Nearest-rank percentiles over the responses in the recent window.
+fn latency_percentiles(recent: &VecDeque<RecentResult>) -> Option<LatencyPercentiles> {
+    let mut latencies: Vec<u64> = recent.iter().filter_map(|r| r.latency_ms).collect();
+    if latencies.is_empty() {
+        return None;
+    }
+    latencies.sort_unstable();
+    let rank = |p: usize| latencies[(p * latencies.len()).div_ceil(100).max(1) - 1];
+    Some(LatencyPercentiles {
+        samples: latencies.len(),
+        p50: rank(50),
+        p95: rank(95),
+        p99: rank(99),
+    })
+}
+
+#[derive(Debug)]
+enum LogCommand {
+    Line(String),
+    Flush(oneshot::Sender<()>),
+    /// Empty the current file; rotated files are left alone
+    Truncate(oneshot::Sender<Result<(), String>>),
+}
+
+#[derive(Debug, Clone)]
+struct LogWriterConfig {
+    path: &'static str,
+    /// Sync to disk after every line
+    fsync: bool,
+    /// Rotate once the file would grow past this size
+    max_bytes: u64,
+    /// Rotated files kept as `<path>.1` (newest) through `<path>.<keep>`
+    keep: usize,
+}
+
+impl LogWriterConfig {
+    fn from_env() -> Self {
+        LogWriterConfig {
+            path: LOG_FILE_PATH,
+            fsync: env_flag("NONAP_LOG_FSYNC"),
+            max_bytes: env_parse("NONAP_LOG_MAX_BYTES", DEFAULT_LOG_MAX_BYTES),
+            keep: env_parse("NONAP_LOG_KEEP", DEFAULT_LOG_KEEP),
+        }
+    }
+}
+
+/// Starts the single thread that appends to the log file. Owning the file in one
+/// place keeps lines in order; the unbounded channel means senders never block.
+fn spawn_log_writer(config: LogWriterConfig) -> mpsc::UnboundedSender<LogCommand> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    std::thread::spawn(move || {
+        let mut file: Option<File> = None;
+        let mut size: u64 = 0;
+
+        while let Some(cmd) = rx.blocking_recv() {
+            match cmd {
+                LogCommand::Line(line) => {
+                    let line_len = line.len() as u64 + 1;
+                    if file.is_some() && config.max_bytes > 0 && size + line_len > config.max_bytes
+                    {
+                        file = None;
+                        rotate_logs(&config);
+                    }
+
+                    // Best effort: reopen on the next line if the file went away
+                    if file.is_none() {
+                        file = OpenOptions::new()
+                            .create(true)
+                            .append(true)
+                            .open(config.path)
+                            .ok();
+                        size = file
+                            .as_ref()
+                            .and_then(|f| f.metadata().ok())
+                            .map_or(0, |m| m.len());
+                    }
+                    if let Some(f) = file.as_mut() {
+                        if writeln!(f, "{}", line).is_err() {
+                            file = None;
+                        } else {
+                            size += line_len;
+                            if config.fsync {
+                                let _ = f.sync_data();
+                            }
+                        }
+                    }
+                }
+                LogCommand::Flush(done) => {
+                    if let Some(f) = file.as_mut() {
+                        let _ = f.sync_data();
+                    }
+                    let _ = done.send(());
+                }
+                LogCommand::Truncate(done) => {
+                    let result = File::create(config.path)
+                        .map_err(|e| format!("Failed to truncate {}: {}", config.path, e));
+                    file = result.as_ref().ok().and_then(|f| f.try_clone().ok());
+                    size = 0;
+                    let _ = done.send(result.map(|_| ()));
+                }
+            }
+        }
+    });
+
+    tx
+}
+
+/// Shifts `<path>.N` to `<path>.N+1` (dropping the oldest) and moves the
+/// current file to `<path>.1`. With `keep == 0` the current file is discarded.
+fn rotate_logs(config: &LogWriterConfig) {
+    if config.keep == 0 {
+        let _ = fs::remove_file(config.path);
+        return;
+    }
+    let _ = fs::remove_file(format!("{}.{}", config.path, config.keep));
+    for n in (1..config.keep).rev() {
+        let _ = fs::rename(
+            format!("{}.{}", config.path, n),
+            format!("{}.{}", config.path, n + 1),
+        );
+    }
+    let _ = fs::rename(config.path, format!("{}.1", config.path));
+}
+
+/// Console highlighting by meaning; the log file and buffers keep the plain text.
+#[derive(Debug, Clone, Copy)]
+enum Style {
+    Success,
+    Failure,
+    Warning,
+    /// Routine lines such as "sleeping"
+    Dim,
+}
+
+impl Style {
+    fn paint(self, msg: &str, enabled: bool) -> Cow<'_, str> {
+        if !enabled {
+            return Cow::Borrowed(msg);
+        }
+        let code = match self {
+            Style::Success => "32",
+            Style::Failure => "31",
+            Style::Warning => "33",
+            Style::Dim => "2",
+        };
+        Cow::Owned(format!("\x1b[{}m{}\x1b[0m", code, msg))
+    }
+}
+
+/// Whether stdout and stderr get colors: only when they are terminals, and
+/// never with `NONAP_NO_COLOR` (or the conventional `NO_COLOR`) set.
+fn console_colors() -> (bool, bool) {
+    static COLORS: OnceLock<(bool, bool)> = OnceLock::new();
+    *COLORS.get_or_init(|| {
+        let off = env_flag("NONAP_NO_COLOR")
+            || std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        (
+            !off && std::io::stdout().is_terminal(),
+            !off && std::io::stderr().is_terminal(),
+        )
+    })
+}
+
+fn print_styled(style: Style, msg: &str) {
+    println!("{}", style.paint(msg, console_colors().0));
+}
+
+fn eprint_styled(style: Style, msg: &str) {
+    eprintln!("{}", style.paint(msg, console_colors().1));
+}
+
+/// Severity of a log entry, ordered so a threshold can be compared with `<`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LogLevel {
+    #[serde(alias = "INFO")]
+    Info,
+    #[serde(alias = "WARN", alias = "warning")]
+    Warn,
+    #[serde(alias = "ERROR")]
+    Error,
+}
+
+impl LogLevel {
+    fn name(self) -> &'static str {
+        match self {
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        }
+    }
+}
+
+impl std::fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "info" => Ok(LogLevel::Info),
+            "warn" | "warning" => Ok(LogLevel::Warn),
+            "error" => Ok(LogLevel::Error),
+            _ => Err("expected info, warn or error".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct LogEntry {
+    /// RFC3339 UTC timestamp
+    timestamp: String,
+    level: LogLevel,
+    /// Target the entry is about, for exact filtering
+    url: Option<String>,
+    message: String,
+}
+
+impl LogEntry {
+    fn line(&self) -> String {
+        format!(
+            "{} {:<5} {}",
+            self.timestamp,
+            self.level.name(),
+            self.message
+        )
+    }
+}
+
+fn append_log(state: SharedState, level: LogLevel, message: String) {
+    push_log(state, None, level, message);
+}
+
+fn append_target_log(state: SharedState, url: &str, level: LogLevel, message: String) {
+    push_log(state, Some(url.to_string()), level, message);
+}
+
+fn push_log(state: SharedState, url: Option<String>, level: LogLevel, message: String) {
+    // Timestamp here so every call site gets the same format
+    let entry = LogEntry {
+        timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        level,
+        url,
+        message,
+    };
+    let line = entry.line();
+    let entry_for_db = entry.clone();
+
+    let mut locked = state.lock();
+    // Below the threshold the entry is dropped from every sink, not just the file
+    if level < locked.log_level {
+        return;
+    }
+
+    // Add to in-memory logs
+    locked.logs.push(entry);
+    let len = locked.logs.len();
+    if len > locked.log_buffer {
+        let excess = len - locked.log_buffer;
+        locked.logs.drain(..excess);
+    }
+    if let Some(history) = &locked.history {
+        let _ = history.send(HistoryCommand::Log(entry_for_db));
+    }
+    // No subscribers is not an error
+    let _ = locked.log_tx.send(line.clone());
+    // Append to log file (best effort, ignore errors)
+    let _ = locked.log_writer.send(LogCommand::Line(line));
+}
+
+/// One completed check as stored in the history database.
+#[derive(Debug, Serialize)]
+struct HistoryRow {
+    timestamp: String,
+    url: String,
+    status: Option<u16>,
+    latency_ms: u64,
+    success: bool,
+}
+
+#[derive(Debug)]
+enum HistoryCommand {
+    Log(LogEntry),
+    Ping(HistoryRow),
+    Query(
+        HistoryQuery,
+        oneshot::Sender<Result<Vec<HistoryRow>, String>>,
+    ),
+}
+
+/// Opens (creating if needed) the SQLite history database and returns it with
+/// the most recent log entries, used to seed the in-memory buffer.
+fn open_history(
+    path: &str,
+    log_buffer: usize,
+) -> Result<(rusqlite::Connection, Vec<LogEntry>), String> {
+    let conn =
+        rusqlite::Connection::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    conn.execute_batch(
+        "PRAGMA journal_mode = WAL;
+         CREATE TABLE IF NOT EXISTS pings (
+             id INTEGER PRIMARY KEY,
+             timestamp TEXT NOT NULL,
+             url TEXT NOT NULL,
+             status INTEGER,
+             latency_ms INTEGER NOT NULL,
+             success INTEGER NOT NULL
+         );
+         CREATE INDEX IF NOT EXISTS pings_url_timestamp ON pings (url, timestamp);
+         CREATE TABLE IF NOT EXISTS logs (
+             id INTEGER PRIMARY KEY,
+             timestamp TEXT NOT NULL,
+             url TEXT,
+             message TEXT NOT NULL,
+             level TEXT
+         );",
+    )
+    .map_err(|e| format!("Failed to initialize {}: {}", path, e))?;
+    // Databases created before log levels lack the column; this fails harmlessly once it exists
+    let _ = conn.execute("ALTER TABLE logs ADD COLUMN level TEXT", []);
+
+    let mut recent = conn
+        .prepare("SELECT timestamp, url, message, level FROM logs ORDER BY id DESC LIMIT ?1")
+        .and_then(|mut stmt| {
+            stmt.query_map([log_buffer as i64], |row| {
+                Ok(LogEntry {
+                    timestamp: row.get(0)?,
+                    // Rows from before log levels were recorded read back as info
+                    level: row
+                        .get::<_, Option<String>>(3)?
+                        .and_then(|level| level.parse().ok())
+                        .unwrap_or(LogLevel::Info),
+                    url: row.get(1)?,
+                    message: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| format!("Failed to read logs from {}: {}", path, e))?;
+    recent.reverse();
+    Ok((conn, recent))
+}
+
+/// Starts the thread that owns the history connection. Like the log writer,
+/// a single owner keeps SQLite access off the async runtime.
+fn spawn_history_writer(conn: rusqlite::Connection) -> mpsc::UnboundedSender<HistoryCommand> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    std::thread::spawn(move || {
+        while let Some(cmd) = rx.blocking_recv() {
+            let result = match cmd {
+                HistoryCommand::Log(entry) => conn
+                    .execute(
+                        "INSERT INTO logs (timestamp, url, message, level) VALUES (?1, ?2, ?3, ?4)",
+                        rusqlite::params![
+                            entry.timestamp,
+                            entry.url,
+                            entry.message,
+                            entry.level.name()
+                        ],
+                    )
+                    .map(|_| ()),
+                HistoryCommand::Ping(row) => conn
+                    .execute(
+                        "INSERT INTO pings (timestamp, url, status, latency_ms, success)
+                         VALUES (?1, ?2, ?3, ?4, ?5)",
+                        rusqlite::params![
+                            row.timestamp,
+                            row.url,
+                            row.status,
+                            row.latency_ms as i64,
+                            row.success
+                        ],
+                    )
+                    .map(|_| ()),
+                HistoryCommand::Query(query, reply) => {
+                    let _ = reply.send(query_history(&conn, &query).map_err(|e| e.to_string()));
+                    Ok(())
+                }
+            };
+            // Logging through append_log would feed straight back into this thread
+            if let Err(e) = result {
+                eprintln!("⚠️ [NoNap] Failed to write history: {}", e);
+            }
+        }
+    });
+
+    tx
+}
+
+fn query_history(
+    conn: &rusqlite::Connection,
+    query: &HistoryQuery,
+) -> rusqlite::Result<Vec<HistoryRow>> {
+    let mut stmt = conn.prepare(
+        "SELECT timestamp, url, status, latency_ms, success FROM pings
+         WHERE (?1 IS NULL OR url = ?1) AND (?2 IS NULL OR timestamp >= ?2)
+         ORDER BY id DESC LIMIT ?3",
+    )?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .min(MAX_HISTORY_LIMIT);
+    let mut rows = stmt
+        .query_map(
+            rusqlite::params![query.url, query.since, limit as i64],
+            |row| {
+                Ok(HistoryRow {
+                    timestamp: row.get(0)?,
+                    url: row.get(1)?,
+                    status: row.get(2)?,
+                    latency_ms: row.get::<_, i64>(3)? as u64,
+                    success: row.get(4)?,
+                })
+            },
+        )?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    // Newest rows are selected so the limit keeps the latest; return them oldest first
+    rows.reverse();
+    Ok(rows)
+}
+
+/// Records a finished check in the history database, if one is configured.
+fn record_history(state: &SharedState, outcome: &PingOutcome) {
+    let locked = state.lock();
+    if let Some(history) = &locked.history {
+        let _ = history.send(HistoryCommand::Ping(HistoryRow {
+            timestamp: history_timestamp(chrono::Utc::now()),
+            url: outcome.url.clone(),
+            status: outcome.status,
+            latency_ms: outcome.latency_ms,
+            success: outcome.success,
+        }));
+    }
+}
+
+/// Fixed-width UTC timestamps compare correctly as strings in SQL.
+fn history_timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+// -- Handlers --
+
+#[derive(Debug, Deserialize)]
+struct StatusQuery {
+    /// Only report targets carrying this tag
+    tag: Option<String>,
+}
+
+async fn handle_status(
+    query: StatusQuery,
+    state: SharedState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let locked = state.lock();
+    let targets: Vec<&PingTarget> = locked
+        .targets
+        .iter()
+        .filter(|t| query.tag.as_deref().is_none_or(|tag| t.has_tag(tag)))
+        .collect();
+    let selected = |url: &String| targets.iter().any(|t| &t.url == url);
+    let remaining: HashMap<&str, u64> = targets
+        .iter()
+        .filter_map(|t| {
+            let stats = locked.stats.get(&t.url).cloned().unwrap_or_default();
+            Some((t.url.as_str(), remaining_pings(t, &stats)?))
+        })
+        .collect();
+    let mut groups: HashMap<&str, (Vec<&str>, usize)> = HashMap::new();
+    for t in &targets {
+        let Some(group) = t.group.as_deref() else {
+            continue;
+        };
+        let entry = groups.entry(group).or_default();
+        entry.0.push(t.url.as_str());
+        if locked
+            .stats
+            .get(&t.url)
+            .is_some_and(|s| s.consecutive_failures > 0)
+        {
+            entry.1 += 1;
+        }
+    }
+    let groups: HashMap<&str, serde_json::Value> = groups
+        .into_iter()
+        .map(|(name, (urls, failing))| {
+            let status = serde_json::json!({
+                "urls": urls,
+                "failing": failing,
+                "healthy": failing == 0,
+            });
+            (name, status)
+        })
+        .collect();
+    let latency: HashMap<&str, LatencyPercentiles> = locked
+        .recent
+        .iter()
+        .filter(|(url, _)| selected(url))
+        .filter_map(|(url, recent)| Some((url.as_str(), latency_percentiles(recent)?)))
+        .collect();
+    let stats: HashMap<&String, &TargetStats> = locked
+        .stats
+        .iter()
+        .filter(|(url, _)| selected(url))
+        .collect();
+    let health: HashMap<&str, HealthState> = targets
+        .iter()
+        .map(|t| {
+            let failures = locked
+                .stats
+                .get(&t.url)
+                .map_or(0, |s| s.consecutive_failures);
+            (t.url.as_str(), health_state(t, failures))
+        })
+        .collect();
+    let next_ping_at: HashMap<&str, String> = locked
+        .next_ping
+        .iter()
+        .filter(|(url, _)| selected(url))
+        .map(|(url, at)| {
+            let at = at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+            (url.as_str(), at)
+        })
+        .collect();
+    // Only targets with a breaker are listed
+    let circuits: HashMap<&str, serde_json::Value> = targets
+        .iter()
+        .filter(|t| t.circuit_breaker.is_some())
+        .map(|t| {
+            let status = match locked.circuits.get(&t.url) {
+                Some(opened_at) => serde_json::json!({
+                    "state": "open",
+                    "opened_at": opened_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                }),
+                None => serde_json::json!({ "state": "closed" }),
+            };
+            (t.url.as_str(), status)
+        })
+        .collect();
+    let resp = serde_json::json!({
+        "running": locked.running,
+        "paused": locked.paused,
+        "latency_window": locked.recent_window,
+        "latency": latency,
+        "health": health,
+        "next_ping_at": next_ping_at,
+        "circuits": circuits,
+        "remaining_pings": remaining,
+        "groups": groups,
+        "targets": targets,
+        "stats": stats,
+        "logs_count": locked.logs.len()
+    });
+    Ok(warp::reply::json(&resp))
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusHistoryQuery {
+    /// Most recent points per target; defaults to the whole window
+    points: Option<usize>,
+}
+
+/// Recent checks per target, oldest first, for charting.
+async fn handle_status_history(
+    query: StatusHistoryQuery,
+    state: SharedState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let locked = state.lock();
+    let series: HashMap<&str, Vec<&RecentResult>> = locked
+        .recent
+        .iter()
+        .map(|(url, recent)| {
+            let skip = query
+                .points
+                .map_or(0, |points| recent.len().saturating_sub(points));
+            (url.as_str(), recent.iter().skip(skip).collect())
+        })
+        .collect();
+    Ok(warp::reply::json(&series))
+}
+
+async fn handle_health(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
+    let locked = state.lock();
+    let worker_count = locked
+        .handles
+        .values()
+        .filter(|(handle, _)| !handle.is_finished())
+        .count();
+    let resp = serde_json::json!({
+        "status": "ok",
+        "uptime_secs": locked.started_at.elapsed().as_secs(),
+        "worker_count": worker_count,
+        "running": locked.running,
+        "paused": locked.paused,
+    });
+    Ok(warp::reply::json(&resp))
+}
+
+#[derive(Serialize)]
+struct ApiResponse {
+    ok: bool,
+    message: String,
+}
+
+type ApiReply = warp::reply::WithStatus<warp::reply::Json>;
+
+/// The `{ "ok", "message" }` body every mutating route replies with; `ok`
+/// follows the status code.
+fn api_reply(status: StatusCode, message: impl Into<String>) -> ApiReply {
+    let body = ApiResponse {
+        ok: status.is_success(),
+        message: message.into(),
+    };
+    warp::reply::with_status(warp::reply::json(&body), status)
+}
+
+async fn handle_start(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut locked = state.lock();
+
+    if locked.running {
+        return Ok(api_reply(StatusCode::BAD_REQUEST, "Already running"));
+    }
+
+    locked.running = true;
+
+    spawn_all_workers(&state, &mut locked);
+
+    Ok(api_reply(StatusCode::OK, "Started pinging"))
+}
+
+async fn handle_stop(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
+    let handles = {
+        let mut locked = state.lock();
+
+        if !locked.running {
+            return Ok(api_reply(StatusCode::BAD_REQUEST, "Already stopped"));
+        }
+
+        locked.running = false;
+        locked.cancel.cancel();
+        locked.cancel = CancellationToken::new();
+        locked.next_ping.clear();
+        std::mem::take(&mut locked.handles)
+    };
+
+    let total = handles.len();
+    let stopped = await_workers(handles).await;
+
+    Ok(api_reply(
+        StatusCode::OK,
+        format!("Stopped pinging ({} of {} workers stopped)", stopped, total),
+    ))
+}
+
+async fn handle_restart(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
+    let handles = {
+        let mut locked = state.lock();
+
+        if !locked.running {
+            return Ok(api_reply(StatusCode::BAD_REQUEST, "Not running"));
+        }
+
+        locked.cancel.cancel();
+        locked.cancel = CancellationToken::new();
+        locked.next_ping.clear();
+        std::mem::take(&mut locked.handles)
+    };
+
+    let total = handles.len();
+    let stopped = await_workers(handles).await;
+
+    let mut locked = state.lock();
+    // A /stop may have landed while the old loops were winding down
+    if !locked.running {
+        return Ok(api_reply(StatusCode::CONFLICT, "Stopped during restart"));
+    }
+    spawn_all_workers(&state, &mut locked);
+    let started = locked.handles.len();
+
+    Ok(api_reply(
+        StatusCode::OK,
+        format!(
+            "Restarted pinging ({} of {} workers stopped, {} started)",
+            stopped, total, started
+        ),
+    ))
+}
+
+async fn handle_pause(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
+    {
+        let mut locked = state.lock();
+        if locked.paused {
+            return Ok(api_reply(StatusCode::BAD_REQUEST, "Already paused"));
+        }
+        locked.paused = true;
+    }
+
+    let msg = "⏸️ [NoNap] Pinging paused".to_string();
+    print_styled(Style::Dim, &msg);
+    append_log(state, LogLevel::Info, msg);
+    Ok(api_reply(StatusCode::OK, "Paused pinging"))
+}
+
+async fn handle_resume(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
+    {
+        let mut locked = state.lock();
+        if !locked.paused {
+            return Ok(api_reply(StatusCode::BAD_REQUEST, "Not paused"));
+        }
+        locked.paused = false;
+    }
+
+    let msg = "▶️ [NoNap] Pinging resumed".to_string();
+    print_styled(Style::Dim, &msg);
+    append_log(state, LogLevel::Info, msg);
+    Ok(api_reply(StatusCode::OK, "Resumed pinging"))
+}
+
+async fn handle_get_targets(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
+    let locked = state.lock();
+    Ok(warp::reply::json(&locked.targets))
+}
+
+/// `GET /config`: targets as they are actually pinged, unlike the raw `/targets`.
+async fn handle_config(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
+    let targets: Vec<PingTarget> = state.lock().targets.iter().map(effective_target).collect();
+    Ok(warp::reply::json(&targets))
+}
+
+const REDACTED: &str = "***";
+
+/// Fills in the defaults a target is pinged with, redacting anything that
+/// may hold a secret: header values, the `basic_auth` password and a
+/// password in the URL. Only the `basic_auth` user has its `${VAR}`s
+/// expanded; the URL is served as configured.
+fn effective_target(target: &PingTarget) -> PingTarget {
+    let mut t = target.clone();
+
+    // Left unexpanded: a ${VAR} can sit anywhere in the URL, query string included
+    if let Ok(mut parsed) = reqwest::Url::parse(&target.url) {
+        if parsed.password().is_some() && parsed.set_password(Some(REDACTED)).is_ok() {
+            t.url = parsed.to_string();
+        }
+    }
+    if let Ok((unit, _)) = delay_unit(target.unit.as_deref()) {
+        t.unit = Some(unit.to_string());
+    }
+    if target.base_delay.is_some() {
+        t.jitter_pct.get_or_insert(0);
+    }
+    if let Ok(method) = parse_method(target.method.as_deref()) {
+        t.method = Some(method.to_string());
+    }
+    if let Ok(check_type) = parse_check_type(target.check_type.as_deref()) {
+        let name = match check_type {
+            CheckType::Http => "http",
+            CheckType::Tcp => "tcp",
+            CheckType::Dns => "dns",
+        };
+        t.check_type = Some(name.to_string());
+    }
+    t.timeout_secs.get_or_insert(DEFAULT_TIMEOUT_SECS);
+    t.retries.get_or_insert(0);
+    t.follow_redirects.get_or_insert(true);
+    t.down_after.get_or_insert(DEFAULT_DOWN_AFTER);
+    if target.max_backoff_mult.is_some() {
+        t.backoff_after.get_or_insert(DEFAULT_BACKOFF_AFTER);
+    }
+    // Grouped targets always run on their group's single loop
+    if target.group.is_none() {
+        t.concurrency.get_or_insert(1);
+    }
+    if target.quiet_hours.is_some() {
+        t.timezone.get_or_insert_with(|| "UTC".to_string());
+    }
+
+    if let Some(headers) = &mut t.headers {
+        for value in headers.values_mut() {
+            *value = REDACTED.to_string();
+        }
+    }
+    if let Some((user, password)) = &mut t.basic_auth {
+        if let Ok(expanded) = expand_env(user) {
+            *user = expanded;
+        }
+        *password = REDACTED.to_string();
+    }
+    t
+}
+
+async fn handle_add_target(
+    new_target: PingTarget,
+    state: SharedState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut locked = state.lock();
+    if let Err(e) = insert_target(&state, &mut locked, new_target) {
+        return Ok(api_reply(StatusCode::BAD_REQUEST, e));
+    }
+
+    // Persist outside the lock since append_log needs it on failure
+    let targets = locked.targets.clone();
+    drop(locked);
+    persist_targets(&state, &targets);
+
+    Ok(api_reply(StatusCode::OK, "Target added"))
+}
+
+#[derive(Serialize)]
+struct AddTargetResult {
+    url: String,
+    ok: bool,
+    message: String,
+}
+
+/// `POST /add-targets`: adds each valid target of the array and reports every
+/// item's outcome in order. The file is written once for the whole batch.
+async fn handle_add_targets(
+    new_targets: Vec<PingTarget>,
+    state: SharedState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut locked = state.lock();
+    let results: Vec<AddTargetResult> = new_targets
+        .into_iter()
+        .map(|target| {
+            let url = target.url.clone();
+            match insert_target(&state, &mut locked, target) {
+                Ok(()) => AddTargetResult {
+                    url,
+                    ok: true,
+                    message: "Target added".to_string(),
+                },
+                Err(message) => AddTargetResult {
+                    url,
+                    ok: false,
+                    message,
+                },
+            }
+        })
+        .collect();
+
+    let targets = results.iter().any(|r| r.ok).then(|| locked.targets.clone());
+    drop(locked);
+    if let Some(targets) = targets {
+        persist_targets(&state, &targets);
+    }
+
+    Ok(warp::reply::json(&results))
+}
+
+/// Validates a new target and adds it, starting its loop if pinging is on.
+/// The caller persists the target list once it has released the lock.
+fn insert_target(
+    state: &SharedState,
+    locked: &mut AppState,
+    mut target: PingTarget,
+) -> Result<(), String> {
+    apply_default_delay(&mut target, locked.default_delay);
+    validate_target(&target).map_err(|e| format!("Invalid target: {}", e))?;
+    if locked.targets.iter().any(|t| t.url == target.url) {
+        return Err("Target already exists".to_string());
+    }
+
+    locked.targets.push(target.clone());
+
+    // Only the new target gets a loop; existing ones keep their timers
+    if locked.running && target.enabled {
+        spawn_worker(state, locked, target);
+    }
+    Ok(())
+}
+
+/// Fills an omitted `min_delay`/`max_delay` from `NONAP_DEFAULT_MIN`/`NONAP_DEFAULT_MAX`,
+/// unless the target uses another delay scheme. The filled values are persisted.
+fn apply_default_delay(target: &mut PingTarget, (min, max): (Option<u64>, Option<u64>)) {
+    if target.interval.is_some() || target.base_delay.is_some() || target.jitter_pct.is_some() {
+        return;
+    }
+    target.min_delay = target.min_delay.or(min);
+    target.max_delay = target.max_delay.or(max);
+}
+
+#[derive(Deserialize)]
+struct RemoveTargetBody {
+    url: String,
+}
+
+async fn handle_remove_target(
+    body: RemoveTargetBody,
+    state: SharedState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(remove_target(&state, &body.url))
+}
+
+/// `GET /targets/{url}/stats`, with the target URL percent-encoded into one segment.
+async fn handle_target_stats(
+    encoded_url: String,
+    state: SharedState,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let Ok(url) = percent_decode_str(&encoded_url).decode_utf8() else {
+        return Ok(Box::new(api_reply(
+            StatusCode::BAD_REQUEST,
+            "Target URL is not valid UTF-8",
+        )));
+    };
+    let locked = state.lock();
+    if !locked.targets.iter().any(|t| t.url == url) {
+        return Ok(Box::new(api_reply(
+            StatusCode::NOT_FOUND,
+            "Target not found",
+        )));
+    }
+    // A target that hasn't been checked yet has all-zero stats
+    let stats = locked.stats.get(url.as_ref()).cloned().unwrap_or_default();
+    Ok(Box::new(warp::reply::json(&stats)))
+}
+
+/// A target's backoff as it stood before `POST /targets/{url}/reset-backoff`.
+#[derive(Debug, Serialize)]
+struct BackoffState {
+    consecutive_failures: u64,
+    /// Factor the base delay was being multiplied by; 1 when not backing off
+    multiplier: u64,
+    /// The target's `max_backoff_mult`, if backoff is enabled for it
+    max_multiplier: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct ResetBackoffReply {
+    ok: bool,
+    message: String,
+    previous: BackoffState,
+}
+
+/// Clears a target's failure streak so it drops straight back to its normal
+/// schedule. Its loop is restarted, since it may be sleeping a backed-off delay.
+async fn handle_reset_backoff(
+    encoded_url: String,
+    state: SharedState,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let Ok(url) = percent_decode_str(&encoded_url).decode_utf8() else {
+        return Ok(Box::new(api_reply(
+            StatusCode::BAD_REQUEST,
+            "Target URL is not valid UTF-8",
+        )));
+    };
+    let mut locked = state.lock();
+    let Some(target) = locked.targets.iter().find(|t| t.url == url).cloned() else {
+        return Ok(Box::new(api_reply(
+            StatusCode::NOT_FOUND,
+            "Target not found",
+        )));
+    };
+
+    let consecutive_failures = locked
+        .stats
+        .entry(target.url.clone())
+        .or_default()
+        .clear_failure_streak();
+    // Grouped targets follow their group's schedule, which never backs off
+    let multiplier = if target.group.is_some() {
+        1
+    } else {
+        backoff_multiplier(
+            target.max_backoff_mult,
+            target.backoff_after.unwrap_or(DEFAULT_BACKOFF_AFTER),
+            consecutive_failures,
+        )
+    };
+    if locked.running && target.enabled && target.group.is_none() && multiplier > 1 {
+        spawn_worker(&state, &mut locked, target.clone());
+    }
+    drop(locked);
+
+    let msg = format!(
+        "🐇 [NoNap] Backoff reset for {} (was x{} after {} consecutive failures)",
+        &target.url, multiplier, consecutive_failures
+    );
+    print_styled(Style::Success, &msg);
+    append_target_log(state.clone(), &target.url, LogLevel::Warn, msg);
+
+    let reply = ResetBackoffReply {
+        ok: true,
+        message: format!("Backoff reset for {}", target.url),
+        previous: BackoffState {
+            consecutive_failures,
+            multiplier,
+            max_multiplier: target.max_backoff_mult,
+        },
+    };
+    Ok(Box::new(warp::reply::json(&reply)))
+}
+
+/// Closes a target's open circuit by hand and clears its failure streak, so it
+/// gets the breaker's full number of failures again before reopening.
+async fn handle_reset_circuit(
+    encoded_url: String,
+    state: SharedState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let Ok(url) = percent_decode_str(&encoded_url).decode_utf8() else {
+        return Ok(api_reply(
+            StatusCode::BAD_REQUEST,
+            "Target URL is not valid UTF-8",
+        ));
+    };
+    let mut locked = state.lock();
+    let Some(target) = locked.targets.iter().find(|t| t.url == url).cloned() else {
+        return Ok(api_reply(StatusCode::NOT_FOUND, "Target not found"));
+    };
+    let Some(opened_at) = locked.circuits.remove(&target.url) else {
+        return Ok(api_reply(StatusCode::BAD_REQUEST, "Circuit is not open"));
+    };
+    locked
+        .stats
+        .entry(target.url.clone())
+        .or_default()
+        .clear_failure_streak();
+    // The loop may be in the middle of a long probe interval
+    if locked.running && target.enabled && target.group.is_none() {
+        spawn_worker(&state, &mut locked, target.clone());
+    }
+    drop(locked);
+
+    let msg = format!(
+        "🔌 [NoNap] Circuit for {} reset by hand (open since {})",
+        &target.url,
+        opened_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+    );
+    print_styled(Style::Success, &msg);
+    append_target_log(state.clone(), &target.url, LogLevel::Warn, msg);
+    Ok(api_reply(
+        StatusCode::OK,
+        format!("Circuit closed for {}", target.url),
+    ))
+}
+
+/// `DELETE /targets/{url}`, with the target URL percent-encoded into one segment.
+async fn handle_delete_target(
+    encoded_url: String,
+    state: SharedState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match percent_decode_str(&encoded_url).decode_utf8() {
+        Ok(url) => Ok(remove_target(&state, &url)),
+        Err(_) => Ok(api_reply(
+            StatusCode::BAD_REQUEST,
+            "Target URL is not valid UTF-8",
+        )),
+    }
+}
+
+fn remove_target(state: &SharedState, url: &str) -> ApiReply {
     let mut locked = state.lock();
 
     let original_len = locked.targets.len();
-    locked.targets.retain(|t| t.url != body.url);
+    locked.targets.retain(|t| t.url != url);
+    forget_target(&mut locked, url);
 
     if locked.targets.len() == original_len {
-        return Ok(warp::reply::with_status(
-            "Target not found",
-            StatusCode::NOT_FOUND,
+        return api_reply(StatusCode::NOT_FOUND, "Target not found");
+    }
+
+    cancel_worker(&mut locked, url);
+
+    let targets = locked.targets.clone();
+    drop(locked);
+    persist_targets(state, &targets);
+
+    api_reply(StatusCode::OK, "Target removed")
+}
+
+#[derive(Deserialize)]
+struct ToggleTargetBody {
+    url: String,
+    enabled: bool,
+}
+
+async fn handle_toggle_target(
+    body: ToggleTargetBody,
+    state: SharedState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut locked = state.lock();
+
+    let target = match locked.targets.iter_mut().find(|t| t.url == body.url) {
+        Some(t) => t,
+        None => {
+            return Ok(api_reply(StatusCode::NOT_FOUND, "Target not found"));
+        }
+    };
+
+    if target.enabled == body.enabled {
+        let msg = if body.enabled {
+            "Target already enabled"
+        } else {
+            "Target already disabled"
+        };
+        return Ok(api_reply(StatusCode::OK, msg));
+    }
+
+    target.enabled = body.enabled;
+    let target = target.clone();
+
+    let msg = if !body.enabled {
+        // Cancelling wakes the worker so it doesn't fire one last request after its sleep
+        cancel_worker(&mut locked, &body.url);
+        "Target disabled"
+    } else {
+        // Only (re)start this target's loop, leaving the others undisturbed
+        if locked.running {
+            spawn_worker(&state, &mut locked, target);
+        }
+        "Target enabled"
+    };
+
+    let targets = locked.targets.clone();
+    drop(locked);
+    persist_targets(&state, &targets);
+
+    Ok(api_reply(StatusCode::OK, msg))
+}
+
+async fn handle_update_target(
+    updated: PingTarget,
+    state: SharedState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Err(e) = validate_target(&updated) {
+        return Ok(api_reply(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid target: {}", e),
         ));
     }
 
-    if locked.running {
-        locked.running = false;
-        locked.handles = vec![];
-        locked.running = true;
+    let mut locked = state.lock();
+
+    let Some(existing) = locked.targets.iter_mut().find(|t| t.url == updated.url) else {
+        return Ok(api_reply(StatusCode::NOT_FOUND, "Target not found"));
+    };
+    *existing = updated.clone();
+
+    // Restart just this target's loop so the new config takes effect
+    cancel_worker(&mut locked, &updated.url);
+    if locked.running && updated.enabled {
+        spawn_worker(&state, &mut locked, updated);
+    }
+
+    let targets = locked.targets.clone();
+    drop(locked);
+    persist_targets(&state, &targets);
+
+    Ok(api_reply(StatusCode::OK, "Target updated"))
+}
+
+#[derive(Deserialize)]
+struct PingNowBody {
+    url: String,
+}
+
+async fn handle_ping_now(
+    body: PingNowBody,
+    state: SharedState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let target = {
+        let locked = state.lock();
+        locked.targets.iter().find(|t| t.url == body.url).cloned()
+    };
+
+    let target = match target {
+        Some(t) => t,
+        None => {
+            return Ok(api_reply(StatusCode::NOT_FOUND, "Target not found"));
+        }
+    };
 
-        let client = Client::new();
-        for target in locked.targets.clone() {
-            let c = client.clone();
-            let s = state.clone();
-            let handle = tokio::spawn(async move { ping_loop(target, c, s).await });
-            locked.handles.push(handle);
+    let spec = match prepare_request(&target, &state) {
+        Ok(spec) => spec,
+        Err(e) => {
+            return Ok(api_reply(StatusCode::BAD_REQUEST, e));
+        }
+    };
+
+    // Runs independently of the target's loop, so its schedule is untouched
+    let client = state.lock().client.clone();
+    let outcome = ping_once(&target, &spec, &client, &state).await;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&outcome),
+        StatusCode::OK,
+    ))
+}
+
+/// Pushes each new log line to a WebSocket client until either side goes away.
+async fn stream_logs(
+    socket: WebSocket,
+    mut rx: broadcast::Receiver<String>,
+    shutdown: CancellationToken,
+) {
+    let (mut ws_tx, mut ws_rx) = socket.split();
+
+    loop {
+        tokio::select! {
+            // Open sockets would otherwise hold up graceful shutdown
+            _ = shutdown.cancelled() => break,
+            line = rx.recv() => match line {
+                Ok(line) => {
+                    if ws_tx.send(Message::text(line)).await.is_err() {
+                        break;
+                    }
+                }
+                // A slow client just misses some lines
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+            incoming = ws_rx.next() => match incoming {
+                Some(Ok(msg)) if msg.is_close() => break,
+                Some(Ok(_)) => {}
+                _ => break,
+            },
+        }
+    }
+
+    let _ = ws_tx.close().await;
+}
+
+/// Turns new log lines into SSE `data:` events, ending at shutdown.
+fn log_events(
+    rx: broadcast::Receiver<String>,
+    shutdown: CancellationToken,
+) -> impl Stream<Item = Result<sse::Event, Infallible>> {
+    stream::unfold((rx, shutdown), |(mut rx, shutdown)| async move {
+        loop {
+            tokio::select! {
+                // Open streams would otherwise hold up graceful shutdown
+                _ = shutdown.cancelled() => return None,
+                line = rx.recv() => match line {
+                    Ok(line) => {
+                        let event = sse::Event::default().data(line);
+                        return Some((Ok(event), (rx, shutdown)));
+                    }
+                    // A slow client just misses some lines
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                },
+            }
+        }
+    })
+}
+
+/// Escapes a Prometheus label value (backslash, double quote and newline).
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+async fn handle_metrics(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
+    use std::fmt::Write as _;
+
+    let locked = state.lock();
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "# HELP nonap_running Whether the pinger is running (1) or stopped (0)."
+    );
+    let _ = writeln!(out, "# TYPE nonap_running gauge");
+    let _ = writeln!(out, "nonap_running {}", u8::from(locked.running));
+
+    let _ = writeln!(
+        out,
+        "# HELP nonap_paused Whether sending pings is paused (1) or not (0)."
+    );
+    let _ = writeln!(out, "# TYPE nonap_paused gauge");
+    let _ = writeln!(out, "nonap_paused {}", u8::from(locked.paused));
+
+    let _ = writeln!(
+        out,
+        "# HELP nonap_pings_total Completed pings per target by result."
+    );
+    let _ = writeln!(out, "# TYPE nonap_pings_total counter");
+    for target in &locked.targets {
+        let stats = locked.stats.get(&target.url).cloned().unwrap_or_default();
+        let url = escape_label(&target.url);
+        let _ = writeln!(
+            out,
+            "nonap_pings_total{{url=\"{}\",result=\"success\"}} {}",
+            url, stats.success_count
+        );
+        let _ = writeln!(
+            out,
+            "nonap_pings_total{{url=\"{}\",result=\"failure\"}} {}",
+            url, stats.failure_count
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP nonap_failures_total Failed checks per target by error category."
+    );
+    let _ = writeln!(out, "# TYPE nonap_failures_total counter");
+    for target in &locked.targets {
+        let Some(stats) = locked.stats.get(&target.url) else {
+            continue;
+        };
+        let url = escape_label(&target.url);
+        for (category, count) in &stats.failures_by_category {
+            let _ = writeln!(
+                out,
+                "nonap_failures_total{{url=\"{}\",category=\"{}\"}} {}",
+                url,
+                category.name(),
+                count
+            );
+        }
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP nonap_last_status HTTP status of the last response per target."
+    );
+    let _ = writeln!(out, "# TYPE nonap_last_status gauge");
+    for target in &locked.targets {
+        if let Some(status) = locked.stats.get(&target.url).and_then(|s| s.last_status) {
+            let _ = writeln!(
+                out,
+                "nonap_last_status{{url=\"{}\"}} {}",
+                escape_label(&target.url),
+                status
+            );
         }
     }
 
-    Ok(warp::reply::with_status("Target removed", StatusCode::OK))
+    Ok(warp::reply::with_header(
+        out,
+        "Content-Type",
+        "text/plain; version=0.0.4",
+    ))
+}
+
+/// `/metrics` as JSON, for consumers that don't speak the Prometheus format.
+#[derive(Debug, Serialize)]
+struct MetricsJson<'a> {
+    running: bool,
+    paused: bool,
+    success_total: u64,
+    failure_total: u64,
+    targets: HashMap<&'a str, TargetMetrics>,
+}
+
+#[derive(Debug, Serialize)]
+struct TargetMetrics {
+    enabled: bool,
+    health: HealthState,
+    /// Only set for targets with a `circuit_breaker`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    circuit_open: Option<bool>,
+    success_total: u64,
+    failure_total: u64,
+    failures_by_category: BTreeMap<ErrorCategory, u64>,
+    consecutive_failures: u64,
+    last_status: Option<u16>,
+    last_latency_ms: Option<u64>,
+    last_checked: Option<String>,
+}
+
+async fn handle_metrics_json(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
+    let locked = state.lock();
+    let targets: HashMap<&str, TargetMetrics> = locked
+        .targets
+        .iter()
+        .map(|target| {
+            let stats = locked.stats.get(&target.url).cloned().unwrap_or_default();
+            let metrics = TargetMetrics {
+                enabled: target.enabled,
+                health: health_state(target, stats.consecutive_failures),
+                circuit_open: target
+                    .circuit_breaker
+                    .as_ref()
+                    .map(|_| locked.circuits.contains_key(&target.url)),
+                success_total: stats.success_count,
+                failure_total: stats.failure_count,
+                failures_by_category: stats.failures_by_category,
+                consecutive_failures: stats.consecutive_failures,
+                last_status: stats.last_status,
+                last_latency_ms: stats.last_latency_ms,
+                last_checked: stats.last_checked,
+            };
+            (target.url.as_str(), metrics)
+        })
+        .collect();
+
+    let metrics = MetricsJson {
+        running: locked.running,
+        paused: locked.paused,
+        success_total: targets.values().map(|m| m.success_total).sum(),
+        failure_total: targets.values().map(|m| m.failure_total).sum(),
+        targets,
+    };
+    Ok(warp::reply::json(&metrics))
 }
 
 #[derive(Deserialize)]
 struct LogQuery {
     tail: Option<usize>,
+    /// Only return entries about this target URL
+    url: Option<String>,
+    /// Only return entries about targets carrying this tag
+    tag: Option<String>,
+    /// Only return entries at or above this level
+    level: Option<LogLevel>,
 }
 
 async fn handle_logs(
@@ -331,51 +4996,382 @@ async fn handle_logs(
     let locked = state.lock();
     let tail = params.tail.unwrap_or(20);
 
-    let logs: Vec<String> = if locked.logs.len() > tail {
-        locked.logs[locked.logs.len() - tail..].to_vec()
+    let matching: Vec<&LogEntry> = locked
+        .logs
+        .iter()
+        .filter(|entry| match &params.url {
+            Some(url) => entry.url.as_ref() == Some(url),
+            None => true,
+        })
+        .filter(|entry| match &params.tag {
+            Some(tag) => entry.url.as_ref().is_some_and(|url| {
+                locked
+                    .targets
+                    .iter()
+                    .any(|t| &t.url == url && t.has_tag(tag))
+            }),
+            None => true,
+        })
+        .filter(|entry| params.level.is_none_or(|level| entry.level >= level))
+        .collect();
+
+    let logs: Vec<String> = matching[matching.len().saturating_sub(tail)..]
+        .iter()
+        .map(|entry| entry.line())
+        .collect();
+
+    Ok(warp::reply::json(&logs))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ClearLogsBody {
+    /// Also truncate the log file
+    file: bool,
+}
+
+/// `POST /logs/clear`; the body is optional and defaults to `{ "file": false }`.
+async fn handle_clear_logs(
+    body: warp::hyper::body::Bytes,
+    state: SharedState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let body: ClearLogsBody = if body.is_empty() {
+        ClearLogsBody::default()
     } else {
-        locked.logs.clone()
+        match serde_json::from_slice(&body) {
+            Ok(body) => body,
+            Err(e) => {
+                return Ok(api_reply(
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid body: {}", e),
+                ))
+            }
+        }
     };
 
-    Ok(warp::reply::json(&logs))
+    let cleared = {
+        let mut locked = state.lock();
+        let cleared = locked.logs.len();
+        locked.logs.clear();
+        cleared
+    };
+
+    if body.file {
+        let (tx, rx) = oneshot::channel();
+        let _ = state.lock().log_writer.send(LogCommand::Truncate(tx));
+        // A dropped sender (writer gone) and a timeout both end up here
+        let result = match tokio::time::timeout(LOG_FLUSH_TIMEOUT, rx).await {
+            Ok(Ok(result)) => result,
+            _ => Err("Log writer did not respond".to_string()),
+        };
+        if let Err(e) = result {
+            eprintln!("❌ [NoNap] {}", e);
+            return Ok(api_reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Cleared {} log lines; {}", cleared, e),
+            ));
+        }
+    }
+
+    let msg = format!(
+        "🧹 [NoNap] Cleared {} log lines{}",
+        cleared,
+        if body.file {
+            format!(" and truncated {}", LOG_FILE_PATH)
+        } else {
+            String::new()
+        }
+    );
+    print_styled(Style::Dim, &msg);
+    append_log(state, LogLevel::Info, msg);
+    Ok(api_reply(
+        StatusCode::OK,
+        format!("Cleared {} log lines", cleared),
+    ))
 }
 
-async fn handle_reload(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
-    match load_targets_from_file("targets.json") {
-        Ok(new_targets) => {
-            let mut locked = state.lock();
-            locked.targets = new_targets;
-
-            if locked.running {
-                locked.running = false;
-                locked.handles.clear();
-                locked.running = true;
-
-                let client = Client::new();
-                for target in locked.targets.clone() {
-                    let c = client.clone();
-                    let s = state.clone();
-                    locked.handles.push(tokio::spawn(async move {
-                        ping_loop(target, c, s).await
-                    }));
-                }
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    url: Option<String>,
+    /// RFC3339 timestamp; only checks at or after it are returned
+    since: Option<String>,
+    limit: Option<usize>,
+}
+
+async fn handle_history(
+    mut query: HistoryQuery,
+    state: SharedState,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let Some(history) = state.lock().history.clone() else {
+        return Ok(Box::new(api_reply(
+            StatusCode::NOT_FOUND,
+            "History is disabled; set NONAP_DB_PATH",
+        )));
+    };
+
+    if let Some(since) = &query.since {
+        match DateTime::parse_from_rfc3339(since) {
+            Ok(since) => query.since = Some(history_timestamp(since.with_timezone(&Utc))),
+            Err(e) => {
+                return Ok(Box::new(api_reply(
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid since {:?}: {}", since, e),
+                )));
             }
+        }
+    }
+
+    let (tx, rx) = oneshot::channel();
+    let _ = history.send(HistoryCommand::Query(query, tx));
+    match rx.await {
+        Ok(Ok(rows)) => Ok(Box::new(warp::reply::json(&rows))),
+        Ok(Err(e)) => Ok(Box::new(api_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to query history: {}", e),
+        ))),
+        Err(_) => Ok(Box::new(api_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "History writer is gone",
+        ))),
+    }
+}
+
+async fn handle_reload(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
+    match reload_targets(&state) {
+        Ok(diff) => Ok(api_reply(
+            StatusCode::OK,
+            format!("Targets reloaded ({})", diff.summary()),
+        )),
+        Err(e) => Ok(api_reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to reload targets: {}", e),
+        )),
+    }
+}
+
+async fn handle_shutdown(
+    allowed: bool,
+    trigger: CancellationToken,
+    state: SharedState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !allowed {
+        return Ok(api_reply(
+            StatusCode::FORBIDDEN,
+            "Remote shutdown is disabled; set NONAP_ALLOW_REMOTE_SHUTDOWN to enable it",
+        ));
+    }
+    if trigger.is_cancelled() {
+        return Ok(api_reply(StatusCode::OK, "Already shutting down"));
+    }
+
+    println!("🛑 [NoNap] Shutdown requested over the API");
+    append_log(
+        state.clone(),
+        LogLevel::Info,
+        "🛑 Shutdown requested over the API".to_string(),
+    );
+    shutdown(state).await;
+    // Graceful shutdown lets this in-flight reply finish before the server exits
+    trigger.cancel();
+    Ok(api_reply(StatusCode::OK, "Shutting down"))
+}
+
+/// Target URLs a reload added, removed or changed the config of.
+#[derive(Debug, Default)]
+struct TargetDiff {
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<String>,
+}
 
-            // Build an HTML<String> reply
-            let reply = warp::reply::html("Targets reloaded".to_string());
-            Ok(warp::reply::with_status(reply, StatusCode::OK))
+impl TargetDiff {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "{} added, {} removed, {} changed",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        )
+    }
+}
+
+fn diff_targets(old: &[PingTarget], new: &[PingTarget]) -> TargetDiff {
+    let mut diff = TargetDiff::default();
+    for target in new {
+        match old.iter().find(|t| t.url == target.url) {
+            None => diff.added.push(target.url.clone()),
+            Some(previous) if previous != target => diff.changed.push(target.url.clone()),
+            Some(_) => {}
+        }
+    }
+    for target in old {
+        if !new.iter().any(|t| t.url == target.url) {
+            diff.removed.push(target.url.clone());
         }
+    }
+    diff
+}
+
+/// Re-reads the config file and applies the difference, logging it. Only
+/// removed and changed targets are stopped and only added and changed ones
+/// started; the rest keep their schedule. Shared by `/reload` and the config
+/// watcher.
+fn reload_targets(state: &SharedState) -> Result<TargetDiff, String> {
+    let config_path = state.lock().config_path.clone();
+    let (new_targets, skipped) = match load_targets_from_file(&config_path) {
+        Ok(loaded) => loaded,
         Err(e) => {
-            // Also build an HTML<String> reply
-            let msg = format!("Failed to reload targets: {}", e);
-            let reply = warp::reply::html(msg);
-            Ok(warp::reply::with_status(reply, StatusCode::INTERNAL_SERVER_ERROR))
+            state.lock().persist_blocked = Some("failed to reload".to_string());
+            return Err(e.to_string());
+        }
+    };
+
+    let diff = {
+        let mut locked = state.lock();
+        locked.persist_blocked = lossy_load_reason(skipped);
+        let diff = diff_targets(&locked.targets, &new_targets);
+        if diff.is_empty() {
+            return Ok(diff);
+        }
+
+        for url in diff.removed.iter().chain(&diff.changed) {
+            cancel_worker(&mut locked, url);
+        }
+        for url in &diff.removed {
+            forget_target(&mut locked, url);
+        }
+        locked.targets = new_targets;
+
+        // Group loops pick up membership changes on their own; spawning a
+        // member just starts its group's loop if none is running
+        if locked.running {
+            let to_start: Vec<PingTarget> = locked
+                .targets
+                .iter()
+                .filter(|t| t.enabled)
+                .filter(|t| diff.added.contains(&t.url) || diff.changed.contains(&t.url))
+                .cloned()
+                .collect();
+            spawn_staggered(state, &mut locked, to_start);
+        }
+        diff
+    };
+
+    let msg = format!("🔄 [NoNap] Reloaded {}: {}", config_path, diff.summary());
+    print_styled(Style::Success, &msg);
+    append_log(state.clone(), LogLevel::Info, msg);
+    let changes = [
+        ("➕", &diff.added),
+        ("➖", &diff.removed),
+        ("✏️", &diff.changed),
+    ];
+    for (icon, urls) in changes {
+        for url in urls {
+            let msg = format!("{} [NoNap] {}", icon, url);
+            print_styled(Style::Dim, &msg);
+            append_target_log(state.clone(), url, LogLevel::Info, msg);
         }
     }
+    Ok(diff)
+}
+
+/// Watches the config file (via its directory, so atomic replaces are seen)
+/// and reloads once events settle. Writes made through the API trigger it too,
+/// but reload to an identical target list and change nothing.
+fn spawn_config_watcher(state: SharedState) -> Result<(), String> {
+    let config_path = state.lock().config_path.clone();
+    let path = Path::new(&config_path);
+    // A config directory is watched for any targets file; a single file via
+    // its parent, so it is still seen after being replaced
+    let (dir, file_name) = if path.is_dir() {
+        (path.to_path_buf(), None)
+    } else {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| format!("{} is not a file path", config_path))?
+            .to_os_string();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => Path::new(".").to_path_buf(),
+        };
+        (dir, Some(file_name))
+    };
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        let Ok(event) = res else {
+            return;
+        };
+        let relevant = matches!(
+            event.kind,
+            notify::EventKind::Create(_)
+                | notify::EventKind::Modify(_)
+                | notify::EventKind::Remove(_)
+        ) && event.paths.iter().any(|p| match &file_name {
+            Some(name) => p.file_name() == Some(name.as_os_str()),
+            None => is_targets_file(p),
+        });
+        if relevant {
+            let _ = tx.send(());
+        }
+    })
+    .map_err(|e| format!("Failed to watch {}: {}", config_path, e))?;
+    notify::Watcher::watch(&mut watcher, &dir, notify::RecursiveMode::NonRecursive)
+        .map_err(|e| format!("Failed to watch {}: {}", config_path, e))?;
+
+    tokio::spawn(async move {
+        // Owned by the task so the watch lives as long as the process
+        let _watcher = watcher;
+        while rx.recv().await.is_some() {
+            // Editors often write in several steps; wait for the burst to end
+            loop {
+                tokio::select! {
+                    _ = sleep(CONFIG_WATCH_DEBOUNCE) => break,
+                    event = rx.recv() => if event.is_none() { return },
+                }
+            }
+            if let Err(e) = reload_targets(&state) {
+                let msg = format!("❌ [NoNap] Failed to reload {}: {}", config_path, e);
+                eprint_styled(Style::Failure, &msg);
+                append_log(state.clone(), LogLevel::Error, msg);
+            }
+        }
+    });
+    Ok(())
 }
 
+/// Serves the dashboard from `path` when set, otherwise the embedded default.
+/// Only this one configured file is ever read, so request paths can't reach
+/// anything else on disk.
+async fn handle_dashboard(path: Option<String>) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let Some(path) = path else {
+        return Ok(Box::new(warp::reply::html(DASHBOARD_HTML)));
+    };
+    match tokio::fs::read_to_string(&path).await {
+        Ok(html) => Ok(Box::new(warp::reply::html(html))),
+        Err(e) => {
+            eprintln!("⚠️ [NoNap] Failed to read dashboard {}: {}", path, e);
+            Ok(Box::new(api_reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to read dashboard {}", path),
+            )))
+        }
+    }
+}
 
+/// Hand-written OpenAPI document; keep it in step with the routes in `main`.
+const OPENAPI_JSON: &str = include_str!("openapi.json");
 
+fn openapi_spec() -> serde_json::Value {
+    let mut spec: serde_json::Value =
+        serde_json::from_str(OPENAPI_JSON).expect("openapi.json is valid JSON");
+    spec["info"]["version"] = env!("CARGO_PKG_VERSION").into();
+    spec
+}
 
 // Dashboard HTML served at /
 const DASHBOARD_HTML: &str = r#"
@@ -389,6 +5385,8 @@ const DASHBOARD_HTML: &str = r#"
   body { font-family: Arial, sans-serif; margin: 20px; }
   h1 { color: #444; }
   #status { margin-bottom: 20px; }
+  .tag { font-weight: bold; margin-top: 6px; }
+  canvas.spark { border: 1px solid #ddd; margin: 2px 0 6px; }
   #logs { white-space: pre-wrap; background: #f0f0f0; padding: 10px; height: 300px; overflow-y: scroll; border: 1px solid #ccc; }
 </style>
 </head>
@@ -398,6 +5396,13 @@ const DASHBOARD_HTML: &str = r#"
   <h2>Recent Logs</h2>
   <div id="logs">Loading logs...</div>
 <script>
+  // Tags, URLs and units come from the API, so they're escaped before going into innerHTML
+  function escapeHtml(value) {
+    return String(value).replace(/[&<>"']/g, c => ({
+      '&': '&amp;', '<': '&lt;', '>': '&gt;', '"': '&quot;', "'": '&#39;'
+    })[c]);
+  }
+
   async function fetchStatus() {
     const res = await fetch('/status');
     if (!res.ok) {
@@ -406,33 +5411,362 @@ const DASHBOARD_HTML: &str = r#"
     }
     const data = await res.json();
     let html = `<b>Running:</b> ${data.running}<br/>`;
-    html += `<b>Targets (${data.targets.length}):</b><ul>`;
+    html += `<b>Targets (${data.targets.length}):</b>`;
+    // A target with several tags is listed under each of them
+    const byTag = new Map();
     data.targets.forEach(t => {
-      html += `<li>${t.url} (delay: ${t.min_delay}-${t.max_delay} mins)</li>`;
+      (t.tags && t.tags.length ? t.tags : ['untagged']).forEach(tag => {
+        if (!byTag.has(tag)) byTag.set(tag, []);
+        byTag.get(tag).push(t);
+      });
+    });
+    const rendered = [];
+    [...byTag.keys()].sort().forEach(tag => {
+      html += `<div class="tag">${escapeHtml(tag)}</div><ul>`;
+      byTag.get(tag).forEach(t => {
+        const delay = t.interval != null ? `every ${t.interval}`
+          : t.base_delay != null ? `${t.base_delay} ±${t.jitter_pct || 0}%`
+          : `${t.min_delay}-${t.max_delay}`;
+        const next = (data.next_ping_at || {})[t.url];
+        html += `<li>${escapeHtml(t.url)} (delay: ${escapeHtml(delay)} ${escapeHtml(t.unit || 'minutes')})`
+          + (next ? `, next ping <span class="countdown" data-at="${escapeHtml(next)}"></span>` : '')
+          + `<br/><canvas class="spark" width="${SPARK_WIDTH}" height="${SPARK_HEIGHT}"></canvas></li>`;
+        rendered.push(t);
+      });
+      html += '</ul>';
     });
-    html += '</ul>';
     html += `<b>Logs count:</b> ${data.logs_count}`;
     document.getElementById('status').innerHTML = html;
+    updateCountdowns();
+
+    const hist = await fetch(`/status/history?points=${SPARK_POINTS}`);
+    if (hist.ok) {
+      const series = await hist.json();
+      const canvases = document.querySelectorAll('#status canvas.spark');
+      rendered.forEach((t, i) => drawSparkline(canvases[i], series[t.url] || []));
+    }
+  }
+
+  function updateCountdowns() {
+    document.querySelectorAll('#status .countdown').forEach(el => {
+      const secs = Math.max(0, Math.round((Date.parse(el.dataset.at) - Date.now()) / 1000));
+      el.textContent = secs > 0 ? `in ${secs}s` : 'due';
+    });
+  }
+
+  const SPARK_POINTS = 50;
+  const SPARK_WIDTH = 200;
+  const SPARK_HEIGHT = 30;
+
+  // Latency line over the recent checks; failed checks are marked in red
+  function drawSparkline(canvas, points) {
+    const ctx = canvas.getContext('2d');
+    ctx.clearRect(0, 0, canvas.width, canvas.height);
+    if (points.length === 0) return;
+    const max = Math.max(1, ...points.map(p => p.latency_ms || 0));
+    const step = canvas.width / Math.max(1, SPARK_POINTS - 1);
+    const y = p => canvas.height - 2 - ((p.latency_ms || 0) / max) * (canvas.height - 4);
+    ctx.strokeStyle = '#4a90d9';
+    ctx.beginPath();
+    points.forEach((p, i) => (i === 0 ? ctx.moveTo(0, y(p)) : ctx.lineTo(i * step, y(p))));
+    ctx.stroke();
+    ctx.fillStyle = '#d9534f';
+    points.forEach((p, i) => {
+      if (!p.success) ctx.fillRect(i * step - 1, 0, 3, canvas.height);
+    });
+  }
+
+  const MAX_LOG_LINES = 20;
+  let logLines = [];
+
+  function renderLogs() {
+    document.getElementById('logs').textContent = logLines.join('\n');
   }
 
   async function fetchLogs() {
-    const res = await fetch('/logs?tail=20');
+    const res = await fetch(`/logs?tail=${MAX_LOG_LINES}`);
     if (!res.ok) {
       document.getElementById('logs').textContent = 'Failed to fetch logs';
       return;
     }
-    const logs = await res.json();
-    document.getElementById('logs').textContent = logs.join('\n');
+    logLines = await res.json();
+    renderLogs();
   }
 
-  async function refresh() {
-    await fetchStatus();
+  // Backfill recent lines, then append new ones as the server pushes them
+  async function connectLogStream() {
     await fetchLogs();
+    const proto = location.protocol === 'https:' ? 'wss' : 'ws';
+    const ws = new WebSocket(`${proto}://${location.host}/logs/ws`);
+    ws.onmessage = (event) => {
+      logLines.push(event.data);
+      if (logLines.length > MAX_LOG_LINES) {
+        logLines.splice(0, logLines.length - MAX_LOG_LINES);
+      }
+      renderLogs();
+    };
+    ws.onclose = () => setTimeout(connectLogStream, 5000); // Reconnect after 5 seconds
   }
 
-  refresh();
-  setInterval(refresh, 5000); // Refresh every 5 seconds
+  fetchStatus();
+  connectLogStream();
+  setInterval(fetchStatus, 5000); // Refresh status every 5 seconds
+  setInterval(updateCountdowns, 1000);
 </script>
 </body>
 </html>
 "#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(url: &str, interval: u64) -> PingTarget {
+        serde_json::from_value(serde_json::json!({ "url": url, "interval": interval }))
+            .expect("valid target")
+    }
+
+    fn utc(hour: u32, minute: u32) -> DateTime<Utc> {
+        chrono::NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+            .and_utc()
+    }
+
+    fn quiet(start: &str, end: &str) -> QuietHours {
+        QuietHours {
+            start: NaiveTime::parse_from_str(start, "%H:%M").unwrap(),
+            end: NaiveTime::parse_from_str(end, "%H:%M").unwrap(),
+            tz: Tz::UTC,
+        }
+    }
+
+    #[test]
+    fn parse_retry_after_seconds() {
+        let cases = [
+            ("0", Some(0)),
+            ("120", Some(120)),
+            (" 30 ", Some(30)),
+            ("999999999", Some(MAX_RETRY_AFTER.as_secs())),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                parse_retry_after(input).map(|d| d.as_secs()),
+                expected,
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn parse_retry_after_http_date() {
+        let future = (Utc::now() + chrono::Duration::seconds(90)).to_rfc2822();
+        let wait = parse_retry_after(&future).expect("future date");
+        assert!((85..=90).contains(&wait.as_secs()), "{:?}", wait);
+
+        let gmt = (Utc::now() + chrono::Duration::seconds(90))
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string();
+        assert!(parse_retry_after(&gmt).is_some(), "{:?}", gmt);
+
+        // Already passed: nothing to wait for
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    }
+
+    #[test]
+    fn parse_retry_after_invalid() {
+        for input in ["", "soon", "-5", "1.5", "Someday, 99 Foo 2015"] {
+            assert_eq!(parse_retry_after(input), None, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn quiet_hours_contains() {
+        let cases = [
+            // Same-day window, end exclusive
+            (("09:00", "17:00"), (8, 59), false),
+            (("09:00", "17:00"), (9, 0), true),
+            (("09:00", "17:00"), (16, 59), true),
+            (("09:00", "17:00"), (17, 0), false),
+            // Crossing midnight
+            (("22:00", "06:00"), (21, 59), false),
+            (("22:00", "06:00"), (22, 0), true),
+            (("22:00", "06:00"), (23, 59), true),
+            (("22:00", "06:00"), (0, 0), true),
+            (("22:00", "06:00"), (5, 59), true),
+            (("22:00", "06:00"), (6, 0), false),
+            (("22:00", "06:00"), (12, 0), false),
+        ];
+        for ((start, end), (hour, minute), expected) in cases {
+            assert_eq!(
+                quiet(start, end).contains(utc(hour, minute)),
+                expected,
+                "{}-{} at {:02}:{:02}",
+                start,
+                end,
+                hour,
+                minute
+            );
+        }
+    }
+
+    #[test]
+    fn quiet_hours_use_the_timezone() {
+        let mut hours = quiet("22:00", "06:00");
+        hours.tz = "Asia/Tokyo".parse().unwrap();
+        // 14:00 UTC is 23:00 in Tokyo
+        assert!(hours.contains(utc(14, 0)));
+        assert!(!hours.contains(utc(22, 0)));
+    }
+
+    #[test]
+    fn escape_label_cases() {
+        let cases = [
+            ("plain", "plain"),
+            ("a\"b", "a\\\"b"),
+            ("a\\b", "a\\\\b"),
+            ("a\nb", "a\\nb"),
+            ("\\\"\n", "\\\\\\\"\\n"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(escape_label(input), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn expand_env_cases() {
+        std::env::set_var("NONAP_TEST_EXPAND_KEY", "hunter2");
+        std::env::set_var("NONAP_TEST_EXPAND_EMPTY", "");
+        std::env::remove_var("NONAP_TEST_EXPAND_UNSET");
+        let cases = [
+            ("no placeholders", Ok("no placeholders")),
+            ("k=${NONAP_TEST_EXPAND_KEY}", Ok("k=hunter2")),
+            (
+                "${NONAP_TEST_EXPAND_KEY}${NONAP_TEST_EXPAND_KEY}",
+                Ok("hunter2hunter2"),
+            ),
+            ("[${NONAP_TEST_EXPAND_EMPTY}]", Ok("[]")),
+            // An unterminated placeholder is left alone
+            ("a${NONAP_TEST_EXPAND_KEY", Ok("a${NONAP_TEST_EXPAND_KEY")),
+            ("$NONAP_TEST_EXPAND_KEY", Ok("$NONAP_TEST_EXPAND_KEY")),
+            (
+                "${NONAP_TEST_EXPAND_UNSET}",
+                Err("environment variable NONAP_TEST_EXPAND_UNSET is not set"),
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                expand_env(input),
+                expected.map(str::to_string).map_err(str::to_string),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn backoff_multiplier_cases() {
+        let cases = [
+            // Disabled without max_backoff_mult
+            ((None, 3, 100), 1),
+            ((Some(8), 3, 0), 1),
+            ((Some(8), 3, 2), 1),
+            ((Some(8), 3, 3), 2),
+            ((Some(8), 3, 4), 4),
+            ((Some(8), 3, 5), 8),
+            ((Some(8), 3, 50), 8),
+            // A threshold of 0 behaves like 1
+            ((Some(8), 0, 0), 1),
+            ((Some(8), 1, 1), 2),
+            ((Some(0), 3, 10), 1),
+            ((Some(u64::MAX), 1, u64::MAX), 1 << 32),
+        ];
+        for ((max_mult, after, failures), expected) in cases {
+            assert_eq!(
+                backoff_multiplier(max_mult, after, failures),
+                expected,
+                "max {:?}, after {}, failures {}",
+                max_mult,
+                after,
+                failures
+            );
+        }
+    }
+
+    #[test]
+    fn latency_percentiles_cases() {
+        let window = |latencies: &[Option<u64>]| -> VecDeque<RecentResult> {
+            latencies
+                .iter()
+                .map(|&latency_ms| RecentResult {
+                    timestamp: String::new(),
+                    success: latency_ms.is_some(),
+                    latency_ms,
+                })
+                .collect()
+        };
+
+        assert!(latency_percentiles(&window(&[])).is_none());
+        assert!(latency_percentiles(&window(&[None, None])).is_none());
+
+        let single = latency_percentiles(&window(&[Some(7), None])).unwrap();
+        assert_eq!(
+            (single.samples, single.p50, single.p95, single.p99),
+            (1, 7, 7, 7)
+        );
+
+        let hundred: Vec<Option<u64>> = (1..=100).rev().map(Some).collect();
+        let p = latency_percentiles(&window(&hundred)).unwrap();
+        assert_eq!((p.samples, p.p50, p.p95, p.p99), (100, 50, 95, 99));
+
+        let p = latency_percentiles(&window(&[Some(10), Some(20), Some(30), Some(40)])).unwrap();
+        assert_eq!((p.p50, p.p95, p.p99), (20, 40, 40));
+    }
+
+    #[test]
+    fn diff_targets_cases() {
+        let a = target("http://a", 1);
+        let b = target("http://b", 1);
+        let c = target("http://c", 1);
+        let b_changed = target("http://b", 2);
+
+        let diff = diff_targets(&[a.clone(), b.clone()], &[a.clone(), b.clone()]);
+        assert!(diff.is_empty());
+
+        let diff = diff_targets(&[a.clone(), b.clone()], &[b_changed, c.clone()]);
+        assert_eq!(diff.added, ["http://c"]);
+        assert_eq!(diff.removed, ["http://a"]);
+        assert_eq!(diff.changed, ["http://b"]);
+        assert_eq!(diff.summary(), "1 added, 1 removed, 1 changed");
+
+        let diff = diff_targets(&[], std::slice::from_ref(&a));
+        assert_eq!(
+            (diff.added.len(), diff.removed.len(), diff.changed.len()),
+            (1, 0, 0)
+        );
+        let diff = diff_targets(&[a], &[]);
+        assert_eq!(
+            (diff.added.len(), diff.removed.len(), diff.changed.len()),
+            (0, 1, 0)
+        );
+    }
+
+    #[test]
+    fn rate_limiter_check() {
+        assert!(RateLimiter::new(0).is_none());
+
+        let limiter = RateLimiter::new(3).unwrap();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        for _ in 0..3 {
+            assert_eq!(limiter.check(ip), Ok(()));
+        }
+        // 3 per minute refills one token every 20 seconds
+        let retry_after = limiter.check(ip).unwrap_err();
+        assert!((1..=20).contains(&retry_after), "{}", retry_after);
+        // Buckets are per client
+        assert_eq!(limiter.check(other), Ok(()));
+    }
+}